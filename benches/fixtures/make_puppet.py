@@ -0,0 +1,125 @@
+"""Generates puppet.inp, the fixture used by the puppet benchmark.
+
+Run from this directory: python3 make_puppet.py
+"""
+
+import json
+import struct
+
+NUM_GROUPS = 10
+PARTS_PER_GROUP = 6
+NUM_PARAMS = 12
+
+
+def transform(x=0.0, y=0.0):
+    return {"trans": [x, y, 0.0], "rot": [0.0, 0.0, 0.0], "scale": [1.0, 1.0]}
+
+
+def node(uuid, name, zsort=0.0, typ="Node", **extra):
+    return {
+        "type": typ,
+        "uuid": uuid,
+        "name": name,
+        "enabled": True,
+        "zsort": zsort,
+        "transform": transform(uuid % 7 * 10.0, uuid % 5 * 10.0),
+        "lockToRoot": False,
+        **extra,
+    }
+
+
+def part(uuid):
+    # 4x4 grid of vertices
+    verts = [float(c) for i in range(16) for c in (i % 4 * 10, i // 4 * 10)]
+    uvs = [float(c) for i in range(16) for c in (i % 4 / 3, i // 4 / 3)]
+    indices = []
+    for y in range(3):
+        for x in range(3):
+            i = y * 4 + x
+            indices += [i, i + 1, i + 4, i + 1, i + 5, i + 4]
+    return node(
+        uuid,
+        f"Part {uuid}",
+        zsort=(uuid % 9 - 4) / 10,
+        typ="Part",
+        blend_mode="Normal",
+        tint=[1.0, 1.0, 1.0],
+        screenTint=[0.0, 0.0, 0.0],
+        mask_threshold=0.5,
+        opacity=1.0,
+        mesh={"verts": verts, "uvs": uvs, "indices": indices, "origin": [0.0, 0.0]},
+        textures=[0, 0, 0],
+    )
+
+
+def binding(param_name, node, values):
+    return {
+        "node": node,
+        "param_name": param_name,
+        "values": values,
+        "isSet": [[True, True], [True, True]],
+        "interpolate_mode": "Linear",
+    }
+
+
+def param(uuid, parts):
+    targets = [parts[(uuid * 5 + i) % len(parts)] for i in range(8)]
+    bindings = []
+    for target in targets:
+        bindings.append(binding("transform.t.x", target, [[-10.0, -10.0], [10.0, 10.0]]))
+        bindings.append(binding("transform.r.z", target, [[-0.5, 0.5], [-0.5, 0.5]]))
+    deform = [[1.0, 1.0]] * 16
+    no_deform = [[0.0, 0.0]] * 16
+    bindings.append(binding("deform", targets[0], [[no_deform, deform], [deform, no_deform]]))
+    return {
+        "uuid": 1000 + uuid,
+        "name": f"Param {uuid}",
+        "is_vec2": True,
+        "min": [-1.0, -1.0],
+        "max": [1.0, 1.0],
+        "defaults": [0.0, 0.0],
+        "axis_points": [[0.0, 1.0], [0.0, 1.0]],
+        "bindings": bindings,
+    }
+
+
+def main():
+    groups = []
+    parts = []
+    uuid = 1
+    for g in range(NUM_GROUPS):
+        group = node(uuid, f"Group {g}", zsort=g / 10)
+        uuid += 1
+        children = []
+        for _ in range(PARTS_PER_GROUP):
+            children.append(part(uuid))
+            parts.append(uuid)
+            uuid += 1
+        group["children"] = children
+        groups.append(group)
+
+    root = node(0, "Root")
+    root["transform"] = transform()
+    root["children"] = groups
+
+    puppet = {
+        "meta": {"name": "Bench puppet", "version": "1.0-alpha", "preservePixels": False},
+        "physics": {"pixelsPerMeter": 1000.0, "gravity": 9.8},
+        "nodes": root,
+        "param": [param(i, parts) for i in range(NUM_PARAMS)],
+    }
+
+    payload = json.dumps(puppet, separators=(",", ":")).encode()
+    # 4x4 RGBA TGA with a top-left origin
+    tga = bytes([0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 4, 0, 32, 0x28]) + bytes([255]) * 64
+
+    data = b"TRNSRTS\0" + struct.pack(">I", len(payload)) + payload
+    data += b"TEX_SECT" + struct.pack(">I", 1)
+    data += struct.pack(">I", len(tga)) + bytes([1]) + tga
+
+    with open("puppet.inp", "wb") as f:
+        f.write(data)
+
+
+if __name__ == "__main__":
+    main()
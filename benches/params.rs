@@ -0,0 +1,134 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+use serde_json::{json, Value};
+
+const NUM_PARTS: u32 = 200;
+const NUM_PARAMS: u32 = 30;
+const PARTS_PER_PARAM: u32 = 40;
+
+fn part(uuid: u32) -> Value {
+    // 3x3 grid of vertices
+    let verts: Vec<f32> = (0..9)
+        .flat_map(|i| [(i % 3) as f32, (i / 3) as f32])
+        .collect();
+    let indices = [
+        0, 1, 3, 1, 4, 3, 1, 2, 4, 2, 5, 4, 3, 4, 6, 4, 7, 6, 4, 5, 7, 5, 8, 7,
+    ];
+
+    json!({
+        "type": "Part",
+        "uuid": uuid,
+        "name": format!("Part {uuid}"),
+        "enabled": true,
+        "zsort": 0.0,
+        "transform": { "trans": [0.0, 0.0, 0.0], "rot": [0.0, 0.0, 0.0], "scale": [1.0, 1.0] },
+        "lockToRoot": false,
+        "blend_mode": "Normal",
+        "tint": [1.0, 1.0, 1.0],
+        "screenTint": [0.0, 0.0, 0.0],
+        "mask_threshold": 0.5,
+        "opacity": 1.0,
+        "mesh": { "verts": verts, "uvs": verts, "indices": indices, "origin": [0.0, 0.0] },
+        "textures": [0, 0, 0],
+    })
+}
+
+fn binding(param_name: &str, node: u32, values: Value) -> Value {
+    json!({
+        "node": node,
+        "param_name": param_name,
+        "values": values,
+        "isSet": [[true, true], [true, true]],
+        "interpolate_mode": "Linear",
+    })
+}
+
+fn param(uuid: u32) -> Value {
+    let bindings: Vec<Value> = (0..PARTS_PER_PARAM)
+        .map(|i| (uuid * 7 + i) % NUM_PARTS + 1)
+        .flat_map(|node| {
+            let deform = vec![[1.0, 1.0]; 9];
+            let no_deform = vec![[0.0, 0.0]; 9];
+            [
+                binding("transform.t.x", node, json!([[-10.0, -10.0], [10.0, 10.0]])),
+                binding("transform.r.z", node, json!([[-0.5, 0.5], [-0.5, 0.5]])),
+                binding(
+                    "deform",
+                    node,
+                    json!([[no_deform, deform], [deform, no_deform]]),
+                ),
+            ]
+        })
+        .collect();
+
+    json!({
+        "uuid": 1000 + uuid,
+        "name": format!("Param {uuid}"),
+        "is_vec2": true,
+        "min": [-1.0, -1.0],
+        "max": [1.0, 1.0],
+        "defaults": [0.0, 0.0],
+        "axis_points": [[0.0, 1.0], [0.0, 1.0]],
+        "bindings": bindings,
+    })
+}
+
+fn complex_puppet() -> Puppet {
+    let puppet = json!({
+        "meta": { "name": "Bench puppet", "version": "1.0-alpha", "preservePixels": false },
+        "physics": { "pixelsPerMeter": 1000.0, "gravity": 9.8 },
+        "nodes": {
+            "type": "Node",
+            "uuid": 0,
+            "name": "Root",
+            "enabled": true,
+            "zsort": 0.0,
+            "transform": { "trans": [0.0, 0.0, 0.0], "rot": [0.0, 0.0, 0.0], "scale": [1.0, 1.0] },
+            "lockToRoot": false,
+            "children": (1..=NUM_PARTS).map(part).collect::<Vec<_>>(),
+        },
+        "param": (0..NUM_PARAMS).map(param).collect::<Vec<_>>(),
+    });
+
+    serde_json::from_value(puppet).unwrap()
+}
+
+fn bench_set_params(c: &mut Criterion) {
+    let puppet = complex_puppet();
+    let updates: Vec<_> = puppet
+        .parameters
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            (
+                param.uuid,
+                Vec2::splat((i as f32 / NUM_PARAMS as f32) - 0.5),
+            )
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("pose");
+    group.bench_function("set_param x30", |b| {
+        b.iter_batched_ref(
+            complex_puppet,
+            |puppet| {
+                for &(uuid, value) in &updates {
+                    puppet.set_param(uuid, value);
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("set_params", |b| {
+        b.iter_batched_ref(
+            complex_puppet,
+            |puppet| puppet.set_params(&updates),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_set_params);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use glam::Vec2;
+use inox2d::math::transform::transform_points;
+use inox2d::parsers::inp::{parse_inp_reader, parse_inp_reuse, NodeTreeScratch};
+
+/// Generated by `fixtures/make_puppet.py`.
+const PUPPET: &[u8] = include_bytes!("fixtures/puppet.inp");
+
+fn bench_parse(c: &mut Criterion) {
+    c.bench_function("parse_inp", |b| {
+        b.iter(|| parse_inp_reader(black_box(PUPPET)).unwrap())
+    });
+
+    let mut scratch = NodeTreeScratch::new();
+    c.bench_function("parse_inp_reuse", |b| {
+        b.iter(|| {
+            let model = parse_inp_reuse(black_box(PUPPET), &mut scratch).unwrap();
+            scratch.recycle(model);
+        })
+    });
+}
+
+fn bench_zsort(c: &mut Criterion) {
+    let model = parse_inp_reader(PUPPET).unwrap();
+    c.bench_function("zsorted", |b| b.iter(|| model.puppet.nodes.zsorted()));
+}
+
+fn bench_pose(c: &mut Criterion) {
+    let model = parse_inp_reader(PUPPET).unwrap();
+    let updates: Vec<_> = model
+        .puppet
+        .parameters
+        .iter()
+        .enumerate()
+        .map(|(i, param)| (param.uuid, Vec2::splat(i as f32 / 10. - 0.5)))
+        .collect();
+
+    c.bench_function("set_params", |b| {
+        b.iter_batched_ref(
+            || parse_inp_reader(PUPPET).unwrap().puppet,
+            |puppet| puppet.set_params(&updates),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_transform(c: &mut Criterion) {
+    let mut puppet = parse_inp_reader(PUPPET).unwrap().puppet;
+    let updates: Vec<_> = puppet
+        .parameters
+        .iter()
+        .map(|param| (param.uuid, Vec2::splat(0.3)))
+        .collect();
+    puppet.set_params(&updates);
+
+    // Every part's vertices with their deforms, and the matrix mapping them to puppet space.
+    // The fixture's meshes have their origin at zero, so the part's world matrix is the one to use.
+    let parts: Vec<_> = puppet
+        .enabled_drawables()
+        .map(|(uuid, part)| {
+            let matrix = puppet.nodes.world_transform(uuid).unwrap().matrix();
+            (matrix, &part.mesh.vertices, &part.deform)
+        })
+        .collect();
+    let mut out = Vec::with_capacity(puppet.vertex_count());
+
+    c.bench_function("transform_point3", |b| {
+        b.iter(|| {
+            out.clear();
+            for &(matrix, points, offsets) in &parts {
+                out.extend(points.iter().enumerate().map(|(i, &point)| {
+                    let offset = offsets.get(i).copied().unwrap_or_default();
+                    matrix
+                        .transform_point3((point + offset).extend(0.))
+                        .truncate()
+                }));
+            }
+            black_box(&out);
+        })
+    });
+    c.bench_function("transform_points", |b| {
+        b.iter(|| {
+            out.clear();
+            for &(matrix, points, offsets) in &parts {
+                transform_points(matrix, points, offsets, &mut out);
+            }
+            black_box(&out);
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_zsort,
+    bench_pose,
+    bench_transform
+);
+criterion_main!(benches);
@@ -1,9 +1,9 @@
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::BufReader;
 use std::path::PathBuf;
 
 use clap::Parser;
-use inox2d::parsers::inp::parse_inp;
+use inox2d::parsers::inp::parse_inp_reader;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -15,16 +15,12 @@ struct Cli {
 fn main() {
     let cli = Cli::parse();
 
-    let data = {
-        let file = File::open(cli.inp_path).unwrap();
-        let mut file = BufReader::new(file);
-        let mut data = Vec::new();
-        file.read_to_end(&mut data).unwrap();
-        data
-    };
+    let file = BufReader::new(File::open(cli.inp_path).unwrap());
+
+    let model = parse_inp_reader(file).unwrap();
+    println!("{:#?}", model.report());
 
-    let model = parse_inp(&data).unwrap().1;
     let puppet = model.puppet;
     let nodes = &puppet.nodes;
     println!("{nodes}");
-}
\ No newline at end of file
+}
@@ -1,11 +1,4 @@
-use std::{
-    env,
-    error::Error,
-    ffi::CString,
-    fs::File,
-    io::{BufReader, Read},
-    num::NonZeroU32,
-};
+use std::{env, error::Error, ffi::CString, fs::File, io::BufReader, num::NonZeroU32};
 
 use glow::HasContext;
 
@@ -17,7 +10,7 @@ use glutin::{
     surface::{GlSurface, Surface, SurfaceAttributesBuilder, WindowSurface},
 };
 
-use inox2d::{parsers::inp::parse_inp, renderers::opengl::OpenglRenderer};
+use inox2d::{parsers::inp::parse_inp_reader, renderers::opengl::OpenglRenderer};
 use raw_window_handle::HasRawWindowHandle;
 
 use tracing::{debug, error, info, warn};
@@ -50,14 +43,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         .init();
 
     info!("Parsing puppet");
-    let data = {
-        let file = File::open(cli.inp_path).unwrap();
-        let mut file = BufReader::new(file);
-        let mut data = Vec::new();
-        file.read_to_end(&mut data).unwrap();
-        data
-    };
-    let model = parse_inp(&data).unwrap().1;
+    let file = BufReader::new(File::open(cli.inp_path).unwrap());
+    let model = parse_inp_reader(file).unwrap();
     let puppet = model.puppet;
     info!(
         "Successfully parsed puppet {:?}",
@@ -74,7 +61,9 @@ fn main() -> Result<(), Box<dyn Error>> {
         window,
     } = launch_opengl_window()?;
 
+    let preserve_pixels = puppet.meta.preserve_pixels;
     let renderer = OpenglRenderer::new(gl, puppet.nodes, model.textures);
+    renderer.set_preserve_pixels(preserve_pixels);
     let zsorted_nodes = renderer.nodes.zsorted();
 
     // Event loop
@@ -0,0 +1,233 @@
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::nodes::node::NodeUuid;
+use crate::params::ParamUuid;
+use crate::puppet::Puppet;
+
+/// How values are interpolated between the keyframes of a lane.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LaneInterpolation {
+    Nearest,
+    #[default]
+    Linear,
+    Stepped,
+    Cubic,
+    Bezier,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Keyframe {
+    pub frame: u32,
+    pub value: f32,
+    /// Tension of the curve going through this keyframe, for cubic and bezier interpolation.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tension: f32,
+}
+
+/// Keyframes of one axis of a parameter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AnimationLane {
+    #[cfg_attr(feature = "serde", serde(rename = "uuid"))]
+    pub param: ParamUuid,
+    /// Axis of the parameter, 0 for X and 1 for Y.
+    #[cfg_attr(feature = "serde", serde(rename = "target"))]
+    pub axis: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub interpolation: LaneInterpolation,
+    /// Keyframes sorted by frame.
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// Keyframe of an [`EnabledTrack`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BoolKeyframe {
+    pub frame: u32,
+    pub value: bool,
+}
+
+/// Keyframes turning a node on and off, e.g. to swap mouth shapes by toggling parts.
+///
+/// Booleans aren't interpolated: the track is always stepped.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EnabledTrack {
+    #[cfg_attr(feature = "serde", serde(rename = "uuid"))]
+    pub node: NodeUuid,
+    /// Keyframes sorted by frame.
+    pub keyframes: Vec<BoolKeyframe>,
+}
+
+impl EnabledTrack {
+    /// Whether the node is enabled at `frame`, holding the value of the last keyframe at or before it.
+    ///
+    /// Before the first keyframe, the value of the first one is used.
+    /// Returns `None` if the track has no keyframes.
+    pub fn sample(&self, frame: f32) -> Option<bool> {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.frame as f32 <= frame);
+        let keyframe = self.keyframes.get(next.saturating_sub(1))?;
+        Some(keyframe.value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct Animation {
+    /// Name the animation is registered under in the puppet.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub name: String,
+    /// Duration of a frame in seconds.
+    pub timestep: f32,
+    /// Whether the animation is added on top of the current parameter values instead of replacing them.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub additive: bool,
+    #[cfg_attr(feature = "serde", serde(default = "default_weight"))]
+    pub animation_weight: f32,
+    /// Length of the animation in frames.
+    pub length: u32,
+    /// Frame the loop starts at, after the lead-in.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub lead_in: Option<u32>,
+    /// Frame the loop ends at, before the lead-out.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub lead_out: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub lanes: Vec<AnimationLane>,
+    /// Tracks of the nodes the animation turns on and off.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub enabled_tracks: Vec<EnabledTrack>,
+}
+
+/// Keyframe driving a parameter outside of its range, see [`CompatReport::out_of_range`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfRangeKeyframe {
+    pub param: ParamUuid,
+    /// Axis of the parameter, 0 for X and 1 for Y.
+    pub axis: usize,
+    pub frame: u32,
+    pub value: f32,
+    /// Range of the parameter on that axis.
+    pub min: f32,
+    pub max: f32,
+}
+
+/// How well an animation fits a puppet, see [`Animation::check_against`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompatReport {
+    /// Parameters the animation drives that the puppet has, sorted.
+    pub resolved_params: Vec<ParamUuid>,
+    /// Parameters the animation drives that the puppet doesn't have, sorted.
+    pub unresolved_params: Vec<ParamUuid>,
+    /// Nodes the animation turns on and off that the puppet doesn't have, sorted.
+    pub unresolved_nodes: Vec<NodeUuid>,
+    /// Keyframes of resolved parameters that are out of their range, and would be clamped.
+    pub out_of_range: Vec<OutOfRangeKeyframe>,
+}
+
+impl CompatReport {
+    /// Whether the animation plays on the puppet as authored.
+    pub fn is_compatible(&self) -> bool {
+        self.unresolved_params.is_empty()
+            && self.unresolved_nodes.is_empty()
+            && self.out_of_range.is_empty()
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_weight() -> f32 {
+    1.
+}
+
+impl Animation {
+    /// Parameters the animation drives, sorted and without duplicates.
+    ///
+    /// Useful to know which parameters to stop tracking live while the animation plays.
+    pub fn target_params(&self) -> Vec<ParamUuid> {
+        let mut params: Vec<_> = self.lanes.iter().map(|lane| lane.param).collect();
+        params.sort();
+        params.dedup();
+        params
+    }
+
+    /// Checks which of the parameters and nodes the animation targets the puppet has,
+    /// and whether the keyframes stay within the ranges of the parameters.
+    pub fn check_against(&self, puppet: &Puppet) -> CompatReport {
+        let mut report = CompatReport::default();
+        for uuid in self.target_params() {
+            if puppet.parameters.iter().any(|param| param.uuid == uuid) {
+                report.resolved_params.push(uuid);
+            } else {
+                report.unresolved_params.push(uuid);
+            }
+        }
+
+        let mut nodes: Vec<_> = self.enabled_tracks.iter().map(|track| track.node).collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes.retain(|&uuid| puppet.nodes.get_node(uuid).is_none());
+        report.unresolved_nodes = nodes;
+
+        for lane in &self.lanes {
+            let Some(param) = puppet
+                .parameters
+                .iter()
+                .find(|param| param.uuid == lane.param)
+            else {
+                continue;
+            };
+            let (min, max) = match lane.axis {
+                0 => (param.min.x, param.max.x),
+                1 => (param.min.y, param.max.y),
+                _ => continue,
+            };
+            for keyframe in &lane.keyframes {
+                if !(min..=max).contains(&keyframe.value) {
+                    report.out_of_range.push(OutOfRangeKeyframe {
+                        param: lane.param,
+                        axis: lane.axis,
+                        frame: keyframe.frame,
+                        value: keyframe.value,
+                        min,
+                        max,
+                    });
+                }
+            }
+        }
+
+        report
+    }
+}
+
+/// Deserializes the puppet's animations, naming them after their key.
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_animations<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Animation>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut animations = BTreeMap::<String, Animation>::deserialize(deserializer)?;
+    for (name, animation) in &mut animations {
+        animation.name.clone_from(name);
+    }
+    Ok(animations)
+}
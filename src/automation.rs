@@ -0,0 +1,46 @@
+use glam::Vec2;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::params::ParamUuid;
+
+/// Output of a driver to one axis of a parameter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DriverBinding {
+    pub param: ParamUuid,
+    /// Axis of the parameter, 0 for X and 1 for Y.
+    pub axis: usize,
+    /// Range of values the driver maps its output to.
+    pub range: Vec2,
+    /// Fields that aren't modeled, kept so that they survive a round trip.
+    #[cfg(feature = "serde")]
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Entry of the puppet's `automation` section, which moves parameters procedurally.
+///
+/// None of them are simulated yet, only [`SimplePhysics`](crate::nodes::drivers::simple_physics::SimplePhysics)
+/// nodes are. They are stored as is so that they aren't lost when re-saving the puppet.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Driver {
+    /// Type of driver, such as `sine` or `physics`.
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub kind: String,
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bindings: Vec<DriverBinding>,
+    /// Settings specific to the type of driver.
+    #[cfg(feature = "serde")]
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Driver {
+    /// Parameters driven by this driver.
+    pub fn params(&self) -> impl Iterator<Item = ParamUuid> + '_ {
+        self.bindings.iter().map(|binding| binding.param)
+    }
+}
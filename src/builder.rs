@@ -0,0 +1,160 @@
+//! Building models in code, e.g. to generate test fixtures or synthesize puppets.
+
+use crate::mesh::Mesh;
+use crate::model::{Model, ModelTexture};
+use crate::nodes::node::{Node, NodeState, NodeUuid};
+use crate::nodes::node_tree::{NodeTree, NodeTreeError};
+use crate::nodes::part::Part;
+use crate::params::{Param, ParamUuid};
+use crate::puppet::{Puppet, PuppetMeta, PuppetPhysics};
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ModelBuilderError {
+    #[error(transparent)]
+    NodeTree(#[from] NodeTreeError),
+    #[error("Part {part:?} uses texture {texture}, but only {num_textures} were added")]
+    MissingTexture {
+        part: NodeUuid,
+        texture: usize,
+        num_textures: usize,
+    },
+    #[error("There already is a parameter with UUID {0:?}")]
+    DuplicateParam(ParamUuid),
+    #[error("Parameter {param:?} has a binding to {node:?}, which wasn't added")]
+    MissingBindingTarget { param: ParamUuid, node: NodeUuid },
+}
+
+/// Builds a [`Model`] piece by piece, checking that what is added refers to things that exist.
+///
+/// Textures must be added before the parts using them, and nodes before the parameters driving them.
+///
+/// ```
+/// # use glam::vec2;
+/// # use inox2d::builder::{ModelBuilder, ModelBuilderError};
+/// # use inox2d::mesh::Mesh;
+/// # use inox2d::model::ModelTexture;
+/// # use inox2d::nodes::node::{NodeState, NodeUuid};
+/// # use inox2d::params::{Binding, BindingBase, InterpolateMode, MergeMode, Param, ParamUuid};
+/// # let png = Vec::new();
+/// let root = NodeUuid::from_raw(1);
+/// let head = NodeUuid::from_raw(2);
+///
+/// let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+/// let texture = builder.add_texture(ModelTexture { format: image::ImageFormat::Png, data: png });
+/// let mesh = Mesh::quad().size(64, 64).build();
+/// builder.add_part(root, NodeState::new(head, "Head"), mesh, [texture; 3])?;
+///
+/// let mut yaw = Param::new(ParamUuid::from_raw(100), "Head:: Yaw", false, vec2(-1., 0.), vec2(1., 0.));
+/// yaw.bindings.push(Binding::TransformTX {
+///     base: BindingBase {
+///         node: head,
+///         is_set: vec![vec![true], vec![true]],
+///         interpolate_mode: InterpolateMode::Linear,
+///         merge_mode: MergeMode::Additive,
+///     },
+///     values: vec![vec![-5.], vec![5.]],
+/// });
+/// builder.add_param(yaw)?;
+///
+/// let model = builder.build();
+/// assert!(model.validate().is_ok());
+/// # Ok::<(), ModelBuilderError>(())
+/// ```
+#[derive(Debug)]
+pub struct ModelBuilder {
+    meta: PuppetMeta,
+    physics: PuppetPhysics,
+    nodes: NodeTree,
+    parameters: Vec<Param>,
+    textures: Vec<ModelTexture>,
+}
+
+impl ModelBuilder {
+    /// Starts a model with only a root node.
+    pub fn new(root: NodeState) -> Self {
+        Self {
+            meta: PuppetMeta::default(),
+            physics: PuppetPhysics::default(),
+            nodes: NodeTree::new(Box::new(root)),
+            parameters: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn with_meta(mut self, meta: PuppetMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn with_physics(mut self, physics: PuppetPhysics) -> Self {
+        self.physics = physics;
+        self
+    }
+
+    /// Adds a texture, returning its index for parts to use.
+    pub fn add_texture(&mut self, texture: ModelTexture) -> usize {
+        self.textures.push(texture);
+        self.textures.len() - 1
+    }
+
+    /// Adds a node as the last child of `parent`.
+    ///
+    /// Fails if `parent` doesn't exist, if the UUID is taken,
+    /// or if the node is a part using a texture that wasn't added.
+    pub fn add_node(
+        &mut self,
+        parent: NodeUuid,
+        node: impl Node,
+    ) -> Result<&mut Self, ModelBuilderError> {
+        if let Some(part) = node.as_any().downcast_ref::<Part>() {
+            if let Some(&texture) = part.textures.iter().find(|&&t| t >= self.textures.len()) {
+                return Err(ModelBuilderError::MissingTexture {
+                    part: part.node_state.uuid,
+                    texture,
+                    num_textures: self.textures.len(),
+                });
+            }
+        }
+        self.nodes.add_node(parent, Box::new(node))?;
+        Ok(self)
+    }
+
+    /// Adds a part with a default drawable state, see [`ModelBuilder::add_node`].
+    pub fn add_part(
+        &mut self,
+        parent: NodeUuid,
+        node_state: NodeState,
+        mesh: Mesh,
+        textures: [usize; 3],
+    ) -> Result<&mut Self, ModelBuilderError> {
+        self.add_node(parent, Part::new(node_state, mesh, textures))
+    }
+
+    /// Adds a parameter.
+    ///
+    /// Fails if the UUID is taken or if one of its bindings drives a node that wasn't added.
+    pub fn add_param(&mut self, param: Param) -> Result<&mut Self, ModelBuilderError> {
+        if self.parameters.iter().any(|p| p.uuid == param.uuid) {
+            return Err(ModelBuilderError::DuplicateParam(param.uuid));
+        }
+        if let Some(binding) = param
+            .bindings
+            .iter()
+            .find(|binding| self.nodes.get_node(binding.node()).is_none())
+        {
+            return Err(ModelBuilderError::MissingBindingTarget {
+                param: param.uuid,
+                node: binding.node(),
+            });
+        }
+        self.parameters.push(param);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Model {
+        Model {
+            puppet: Puppet::new(self.meta, self.physics, self.nodes, self.parameters),
+            textures: self.textures,
+        }
+    }
+}
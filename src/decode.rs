@@ -0,0 +1,430 @@
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+use image::{DynamicImage, ImageError, ImageFormat, ImageOutputFormat, RgbaImage};
+
+use crate::model::{texture_roles, Model, ModelTexture, TextureRole};
+use crate::puppet::Puppet;
+
+/// How the color values of a texture should be interpreted when sampling it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Colors are gamma-encoded, e.g. `Rgba8UnormSrgb` in wgpu.
+    Srgb,
+    /// Values are used as is, e.g. `Rgba8Unorm` in wgpu.
+    Linear,
+}
+
+impl From<TextureRole> for ColorSpace {
+    fn from(role: TextureRole) -> Self {
+        match role {
+            TextureRole::Albedo | TextureRole::Emissive => ColorSpace::Srgb,
+            TextureRole::Bump => ColorSpace::Linear,
+        }
+    }
+}
+
+/// A texture decoded to RGBA pixels.
+#[derive(Clone, Debug)]
+pub struct DecodedTexture {
+    pub image: RgbaImage,
+    /// Color space the pixels are in, derived from the role of the texture.
+    ///
+    /// Textures that aren't used by any part are assumed to be sRGB.
+    pub color_space: ColorSpace,
+}
+
+impl DecodedTexture {
+    /// Mip levels of the texture below its full size, each half the size of the previous one
+    /// (rounded down) until 1×1, see [`downscale_half`].
+    pub fn mip_chain(&self) -> Vec<RgbaImage> {
+        let mut levels: Vec<RgbaImage> = Vec::new();
+        loop {
+            let level = levels.last().unwrap_or(&self.image);
+            if level.width() <= 1 && level.height() <= 1 {
+                return levels;
+            }
+            let next = downscale_half(level, self.color_space);
+            levels.push(next);
+        }
+    }
+}
+
+/// [`srgb_to_linear`] for every byte value, computed on first use.
+fn srgb_to_linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|value| srgb_to_linear(value as u8)))
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    };
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// Halves the size of an image, rounding down, by averaging blocks of 2×2 pixels.
+///
+/// sRGB images are averaged in linear space with premultiplied alpha,
+/// so that edges don't darken and transparent pixels don't bleed their color into opaque ones.
+/// Linear images, e.g. bump maps, are averaged as is.
+///
+/// Empty images stay empty, as there are no pixels to average.
+pub fn downscale_half(image: &RgbaImage, color_space: ColorSpace) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return RgbaImage::new(width / 2, height / 2);
+    }
+    let to_linear = srgb_to_linear_table();
+
+    RgbaImage::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+        let xs = [2 * x, (2 * x + 1).min(width - 1)];
+        let ys = [2 * y, (2 * y + 1).min(height - 1)];
+        let mut sum = [0f32; 4];
+        for y in ys {
+            for x in xs {
+                let [r, g, b, a] = image.get_pixel(x, y).0;
+                match color_space {
+                    ColorSpace::Srgb => {
+                        let alpha = a as f32 / 255.;
+                        for (sum, value) in sum.iter_mut().zip([r, g, b]) {
+                            *sum += to_linear[value as usize] * alpha;
+                        }
+                        sum[3] += alpha;
+                    }
+                    ColorSpace::Linear => {
+                        for (sum, value) in sum.iter_mut().zip([r, g, b, a]) {
+                            *sum += value as f32 / 255.;
+                        }
+                    }
+                }
+            }
+        }
+
+        let [r, g, b, a] = sum.map(|sum| sum / 4.);
+        match color_space {
+            ColorSpace::Srgb if a > 0. => image::Rgba([
+                linear_to_srgb(r / a),
+                linear_to_srgb(g / a),
+                linear_to_srgb(b / a),
+                (a * 255.).round() as u8,
+            ]),
+            ColorSpace::Srgb => image::Rgba([0; 4]),
+            ColorSpace::Linear => image::Rgba([r, g, b, a].map(|v| (v * 255.).round() as u8)),
+        }
+    })
+}
+
+/// Error of [`ModelTexture::transcode`] and [`Model::transcode_all`].
+///
+/// Only PNG and TGA can be transcoded to. BC7 was left out on purpose:
+/// encoding it needs a block compressor, which `image` doesn't have,
+/// and textures can still be compressed for the GPU after being decoded.
+#[derive(thiserror::Error, Debug)]
+pub enum TranscodeError {
+    /// The target format isn't PNG or TGA, e.g. BC7.
+    #[error("Textures can't be transcoded to {0:?}, only to PNG and TGA")]
+    UnsupportedFormat(ImageFormat),
+    #[error("Could not transcode the texture: {0}")]
+    Image(#[from] ImageError),
+}
+
+/// Where the first row of a TGA texture is, see [`ModelTexture::decode_with`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TgaOrigin {
+    /// As given by the origin bits of the file.
+    #[default]
+    FromHeader,
+    /// At the top, whatever the file says, for exporters that mislabel their textures.
+    Top,
+    /// At the bottom, whatever the file says.
+    Bottom,
+}
+
+/// Offset of the image descriptor byte in the header of TGA files.
+const TGA_DESCRIPTOR: usize = 17;
+/// Bit of the image descriptor set when the first row is at the top.
+const TGA_TOP_ORIGIN: u8 = 0x20;
+/// Bit of the image descriptor set when rows go from right to left.
+const TGA_RIGHT_ORIGIN: u8 = 0x10;
+
+impl ModelTexture {
+    /// Decodes the texture to RGBA pixels, with the first row at the top and going left to right like PNG.
+    pub fn decode(&self) -> Result<RgbaImage, ImageError> {
+        self.decode_with(TgaOrigin::FromHeader)
+    }
+
+    /// Decodes the texture like [`ModelTexture::decode`], with the origin of TGA textures given by `origin`.
+    ///
+    /// Other formats ignore `origin`.
+    pub fn decode_with(&self, origin: TgaOrigin) -> Result<RgbaImage, ImageError> {
+        let mut image = image::load_from_memory_with_format(&self.data, self.format)?.into_rgba8();
+        if self.format != ImageFormat::Tga {
+            return Ok(image);
+        }
+
+        // `image` flips bottom origin textures, but ignores right to left ones
+        let descriptor = self
+            .data
+            .get(TGA_DESCRIPTOR)
+            .copied()
+            .unwrap_or(TGA_TOP_ORIGIN);
+        let header_bottom = descriptor & TGA_TOP_ORIGIN == 0;
+        let bottom = match origin {
+            TgaOrigin::FromHeader => header_bottom,
+            TgaOrigin::Top => false,
+            TgaOrigin::Bottom => true,
+        };
+        if bottom != header_bottom {
+            image::imageops::flip_vertical_in_place(&mut image);
+        }
+        if descriptor & TGA_RIGHT_ORIGIN != 0 {
+            image::imageops::flip_horizontal_in_place(&mut image);
+        }
+        Ok(image)
+    }
+
+    /// Decodes the texture and encodes it again as `to`, which must be a format `.inp` files can store.
+    ///
+    /// BC7 isn't supported, as there is no block compressor to encode it.
+    /// A texture that already is in the target format is returned as is.
+    pub fn transcode(&self, to: ImageFormat) -> Result<ModelTexture, TranscodeError> {
+        if !matches!(to, ImageFormat::Png | ImageFormat::Tga) {
+            return Err(TranscodeError::UnsupportedFormat(to));
+        }
+        if self.format == to {
+            return Ok(self.clone());
+        }
+
+        let mut data = Vec::new();
+        DynamicImage::ImageRgba8(self.decode()?)
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::from(to))?;
+        Ok(ModelTexture { format: to, data })
+    }
+}
+
+/// A texture of a model waiting to be decoded, see [`Model::decode_jobs`].
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeJob<'a> {
+    pub texture: &'a ModelTexture,
+    pub color_space: ColorSpace,
+}
+
+impl DecodeJob<'_> {
+    pub fn decode(self) -> Result<DecodedTexture, ImageError> {
+        Ok(DecodedTexture {
+            image: self.texture.decode()?,
+            color_space: self.color_space,
+        })
+    }
+}
+
+/// A model whose textures are decoded, ready to be uploaded to the GPU.
+#[derive(Debug)]
+pub struct DecodedModel {
+    pub puppet: Puppet,
+    pub textures: Vec<DecodedTexture>,
+}
+
+impl DecodedModel {
+    /// Makes a model out of textures decoded outside of this crate,
+    /// e.g. by the browser's `createImageBitmap` to keep the `image` decoders out of a wasm bundle.
+    ///
+    /// `images` must be in the same order as the textures of the `.inp` file,
+    /// their color space is derived from the role of the textures like in [`Model::decode_textures`].
+    pub fn from_raw(puppet: Puppet, images: Vec<RgbaImage>) -> Self {
+        let roles = texture_roles(&puppet, images.len());
+        let textures = images
+            .into_iter()
+            .zip(roles)
+            .map(|(image, role)| DecodedTexture {
+                image,
+                color_space: role.map_or(ColorSpace::Srgb, ColorSpace::from),
+            })
+            .collect();
+        Self { puppet, textures }
+    }
+
+    /// Decodes the textures of a model in parallel on `pool`,
+    /// see [`Model::into_decoded`] to decode them one after the other.
+    #[cfg(feature = "rayon")]
+    pub fn decode_on(model: Model, pool: &rayon::ThreadPool) -> Result<Self, ImageError> {
+        use rayon::prelude::*;
+
+        let jobs: Vec<_> = model.decode_jobs().collect();
+        let textures = pool.install(|| {
+            jobs.into_par_iter()
+                .map(DecodeJob::decode)
+                .collect::<Result<_, _>>()
+        })?;
+        Ok(Self {
+            puppet: model.puppet,
+            textures,
+        })
+    }
+}
+
+impl Model {
+    /// Decodes the textures of the model, see [`Model::decode_textures`].
+    pub fn into_decoded(self) -> Result<DecodedModel, ImageError> {
+        let textures = self.decode_textures()?;
+        Ok(DecodedModel {
+            puppet: self.puppet,
+            textures,
+        })
+    }
+
+    /// Decodes all the textures of the model one after the other, tagging them with their color space.
+    pub fn decode_textures(&self) -> Result<Vec<DecodedTexture>, ImageError> {
+        self.decode_jobs().map(DecodeJob::decode).collect()
+    }
+
+    /// Transcodes all the textures of the model to `to`, see [`ModelTexture::transcode`].
+    ///
+    /// If one of them fails, the model is left untouched.
+    pub fn transcode_all(&mut self, to: ImageFormat) -> Result<(), TranscodeError> {
+        self.textures = self
+            .textures
+            .iter()
+            .map(|texture| texture.transcode(to))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// One job per texture, in order, to decode them on threads of your own.
+    pub fn decode_jobs(&self) -> impl Iterator<Item = DecodeJob<'_>> + '_ {
+        self.textures
+            .iter()
+            .zip(self.texture_roles())
+            .map(|(texture, role)| DecodeJob {
+                texture,
+                color_space: role.map_or(ColorSpace::Srgb, ColorSpace::from),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn texture(
+        width: u32,
+        height: u32,
+        pixels: &[[u8; 4]],
+        color_space: ColorSpace,
+    ) -> DecodedTexture {
+        let image =
+            RgbaImage::from_fn(width, height, |x, y| Rgba(pixels[(y * width + x) as usize]));
+        DecodedTexture { image, color_space }
+    }
+
+    #[test]
+    fn srgb_edges_average_without_darkening_or_halos() {
+        let red = [255, 0, 0, 255];
+        let clear = [0; 4];
+        let edge = texture(2, 2, &[red, clear, red, clear], ColorSpace::Srgb);
+        assert_eq!(edge.mip_chain()[0].get_pixel(0, 0).0, [255, 0, 0, 128]);
+
+        // Black and white average to linear half gray, lighter than the sRGB midpoint
+        let (black, white) = ([0, 0, 0, 255], [255; 4]);
+        let checker = texture(2, 2, &[black, white, white, black], ColorSpace::Srgb);
+        assert_eq!(
+            checker.mip_chain()[0].get_pixel(0, 0).0,
+            [188, 188, 188, 255]
+        );
+
+        // Linear textures are averaged as is
+        let clear_white = [255, 255, 255, 0];
+        let bump = texture(
+            2,
+            2,
+            &[red, clear_white, red, clear_white],
+            ColorSpace::Linear,
+        );
+        assert_eq!(bump.mip_chain()[0].get_pixel(0, 0).0, [255, 128, 128, 128]);
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_pixel() {
+        let texture = texture(5, 2, &[[255; 4]; 10], ColorSpace::Srgb);
+        let sizes: Vec<_> = texture
+            .mip_chain()
+            .iter()
+            .map(RgbaImage::dimensions)
+            .collect();
+        assert_eq!(sizes, [(2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn empty_textures_stay_empty() {
+        let empty = texture(0, 4, &[], ColorSpace::Srgb);
+        let sizes: Vec<_> = empty
+            .mip_chain()
+            .iter()
+            .map(RgbaImage::dimensions)
+            .collect();
+        assert_eq!(sizes, [(0, 2), (0, 1)]);
+    }
+
+    /// Uncompressed 2x2 TGA with the given image descriptor bits,
+    /// whose rows are stored as `[10, 20]` then `[30, 40]` in the red channel.
+    fn tga(descriptor: u8) -> ModelTexture {
+        let mut data = vec![0; 18];
+        // Uncompressed truecolor
+        data[2] = 2;
+        // 2x2
+        data[12] = 2;
+        data[14] = 2;
+        // 32 bits per pixel, 8 of them alpha
+        data[16] = 32;
+        data[17] = 8 | descriptor;
+        for red in [10, 20, 30, 40] {
+            // BGRA
+            data.extend([0, 0, red, 255]);
+        }
+        ModelTexture {
+            format: ImageFormat::Tga,
+            data,
+        }
+    }
+
+    fn reds(texture: &ModelTexture, origin: TgaOrigin) -> Vec<u8> {
+        let image = texture.decode_with(origin).unwrap();
+        image.pixels().map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn bottom_origin_tga_is_flipped_to_top() {
+        let bottom = tga(0);
+        assert_eq!(reds(&bottom, TgaOrigin::FromHeader), [30, 40, 10, 20]);
+
+        let top = tga(TGA_TOP_ORIGIN);
+        assert_eq!(reds(&top, TgaOrigin::FromHeader), [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn right_origin_tga_is_flipped_to_left() {
+        let texture = tga(TGA_TOP_ORIGIN | TGA_RIGHT_ORIGIN);
+        assert_eq!(reds(&texture, TgaOrigin::FromHeader), [20, 10, 40, 30]);
+    }
+
+    #[test]
+    fn tga_origin_overrides_header() {
+        let bottom = tga(0);
+        assert_eq!(reds(&bottom, TgaOrigin::Top), [10, 20, 30, 40]);
+        assert_eq!(reds(&bottom, TgaOrigin::Bottom), [30, 40, 10, 20]);
+    }
+}
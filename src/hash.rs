@@ -0,0 +1,33 @@
+/// FNV-1a hasher, for hashes that have to stay the same across runs and versions of the program,
+/// which the std hashers don't guarantee.
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Writes `bytes` after their length, so that consecutive fields can't be mistaken for one another.
+    #[cfg(feature = "serde")]
+    pub(crate) fn write_field(&mut self, bytes: &[u8]) {
+        self.write_bytes(&(bytes.len() as u64).to_le_bytes());
+        self.write_bytes(bytes);
+    }
+
+    /// Writes the JSON serialization of `value` as a field.
+    #[cfg(feature = "serde")]
+    pub(crate) fn write_json(&mut self, value: &impl serde::Serialize) {
+        let json = serde_json::to_vec(value).expect("puppets always serialize to JSON");
+        self.write_field(&json);
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
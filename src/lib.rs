@@ -1,9 +1,18 @@
 pub const INOCHI2D_SPEC_VERSION: &str = "1.0-alpha";
 
+pub mod animation;
+pub mod automation;
+pub mod builder;
+pub mod decode;
+mod hash;
 pub mod math;
 pub mod mesh;
 pub mod model;
 pub mod nodes;
+pub mod params;
+#[cfg(feature = "parse")]
 pub mod parsers;
+pub mod pose;
 pub mod puppet;
 pub mod renderers;
+pub mod scene;
@@ -0,0 +1,48 @@
+use glam::Vec2;
+
+/// Smallest convex polygon containing all the points, using Andrew's monotone chain.
+///
+/// Vertices are in counter-clockwise order with the Y axis pointing up,
+/// so clockwise in puppet space where it points down, starting from the leftmost one.
+/// Points lying on an edge are left out. Fewer than three points are returned as is, without duplicates.
+///
+/// ```
+/// # use glam::vec2;
+/// # use inox2d::math::hull::convex_hull;
+/// let points = [vec2(0., 0.), vec2(2., 0.), vec2(1., 1.), vec2(2., 2.), vec2(0., 2.), vec2(1., 0.)];
+/// assert_eq!(
+///     convex_hull(&points),
+///     [vec2(0., 0.), vec2(2., 0.), vec2(2., 2.), vec2(0., 2.)]
+/// );
+/// ```
+pub fn convex_hull(points: &[Vec2]) -> Vec<Vec2> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    // Whether going from `a` to `b` then `c` doesn't turn counter-clockwise
+    let not_ccw = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - a) <= 0.;
+
+    let mut hull: Vec<Vec2> = Vec::with_capacity(points.len() + 1);
+    // Lower half
+    for &point in &points {
+        while hull.len() >= 2 && not_ccw(hull[hull.len() - 2], hull[hull.len() - 1], point) {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    // Upper half, going back without removing the lower half
+    let lower_len = hull.len();
+    for &point in points.iter().rev().skip(1) {
+        while hull.len() > lower_len && not_ccw(hull[hull.len() - 2], hull[hull.len() - 1], point) {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    // The last point is the first one again
+    hull.pop();
+    hull
+}
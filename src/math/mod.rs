@@ -1 +1,4 @@
+pub mod hull;
+pub mod rect;
+pub mod spline;
 pub mod transform;
@@ -0,0 +1,38 @@
+use glam::Vec2;
+
+/// Axis-aligned rectangle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Rect {
+    /// Smallest rectangle containing all the points, or `None` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec2>) -> Option<Rect> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(
+            Rect {
+                min: first,
+                max: first,
+            },
+            |rect, point| Rect {
+                min: rect.min.min(point),
+                max: rect.max.max(point),
+            },
+        ))
+    }
+
+    /// Smallest rectangle containing both rectangles.
+    pub fn union(self, other: Rect) -> Rect {
+        Rect {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+}
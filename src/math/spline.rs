@@ -0,0 +1,86 @@
+use glam::Vec2;
+
+/// Number of samples per segment used to approximate the length of a spline.
+const LENGTH_SAMPLES: usize = 16;
+
+/// Point at `t` in `[0, 1]` on the Catmull-Rom segment going from `p1` to `p2`.
+pub fn catmull_rom_segment(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, t: f32) -> Vec2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2. * p1)
+        + (p2 - p0) * t
+        + (2. * p0 - 5. * p1 + 4. * p2 - p3) * t2
+        + (3. * p1 - p0 - 3. * p2 + p3) * t3)
+}
+
+/// Point at `t` in `[0, 1]` on the Catmull-Rom spline going through all the `points`.
+///
+/// Every segment between two consecutive points covers the same range of `t`,
+/// and the end points are repeated so the spline starts and stops at them.
+/// Returns `Vec2::ZERO` if there are no points.
+pub fn catmull_rom(points: &[Vec2], t: f32) -> Vec2 {
+    match points {
+        [] => Vec2::ZERO,
+        [point] => *point,
+        _ => {
+            let segments = points.len() - 1;
+            let t = t.clamp(0., 1.) * segments as f32;
+            let i = (t as usize).min(segments - 1);
+
+            let p1 = points[i];
+            let p2 = points[i + 1];
+            let p0 = if i > 0 { points[i - 1] } else { p1 };
+            let p3 = points.get(i + 2).copied().unwrap_or(p2);
+            catmull_rom_segment(p0, p1, p2, p3, t - i as f32)
+        }
+    }
+}
+
+/// Samples `count` points evenly spaced along the Catmull-Rom spline going through `points`.
+///
+/// Unlike sampling [`catmull_rom`] at evenly spaced `t`,
+/// the distance between the samples doesn't depend on how far apart the points are.
+/// The first and last samples are the end points.
+pub fn resample(points: &[Vec2], count: usize) -> Vec<Vec2> {
+    if count == 0 || points.is_empty() {
+        return Vec::new();
+    }
+    if count == 1 || points.len() == 1 {
+        return vec![points[0]; count];
+    }
+
+    // Table of (t, distance along the spline) to map distances back to t
+    let steps = (points.len() - 1) * LENGTH_SAMPLES;
+    let mut table = Vec::with_capacity(steps + 1);
+    let mut length = 0.;
+    let mut prev = points[0];
+    table.push((0., 0.));
+    for step in 1..=steps {
+        let t = step as f32 / steps as f32;
+        let point = catmull_rom(points, t);
+        length += point.distance(prev);
+        table.push((t, length));
+        prev = point;
+    }
+
+    if length == 0. {
+        return vec![points[0]; count];
+    }
+
+    let mut samples = Vec::with_capacity(count);
+    let mut entry = 0;
+    for i in 0..count {
+        let distance = length * i as f32 / (count - 1) as f32;
+        while entry + 2 < table.len() && table[entry + 1].1 < distance {
+            entry += 1;
+        }
+        let ((t0, d0), (t1, d1)) = (table[entry], table[entry + 1]);
+        let t = if d1 > d0 {
+            t0 + (t1 - t0) * ((distance - d0) / (d1 - d0)).clamp(0., 1.)
+        } else {
+            t0
+        };
+        samples.push(catmull_rom(points, t));
+    }
+    samples
+}
@@ -1,24 +1,32 @@
 use std::ops::Mul;
 
 use glam::{EulerRot, Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Very dumb function that is only used by Serde for `pixel_snap` serialization
+#[cfg(feature = "serde")]
 fn is_false(value: &bool) -> bool {
     !value
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Transform {
-    #[serde(skip)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     trs: Mat4,
-    #[serde(rename = "trans")]
+    #[cfg_attr(feature = "serde", serde(rename = "trans"))]
     pub translation: Vec3,
-    #[serde(rename = "rot")]
+    #[cfg_attr(feature = "serde", serde(rename = "rot"))]
     pub rotation: Vec3,
     pub scale: Vec2,
     /// Whether the transform should snap to pixels
-    #[serde(default, skip_serializing_if = "is_false")]
+    ///
+    /// The final translation of nodes with this set is rounded to whole pixels, see [`snap_to_pixels`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, alias = "pixelSnap", skip_serializing_if = "is_false")
+    )]
     pub pixel_snap: bool,
 }
 
@@ -34,6 +42,16 @@ impl Default for Transform {
     }
 }
 
+/// Compares everything but the cached matrix, which may not be up to date.
+impl PartialEq for Transform {
+    fn eq(&self, other: &Self) -> bool {
+        self.translation == other.translation
+            && self.rotation == other.rotation
+            && self.scale == other.scale
+            && self.pixel_snap == other.pixel_snap
+    }
+}
+
 impl Transform {
     pub fn new() -> Self {
         Self::default()
@@ -58,6 +76,47 @@ impl Transform {
         self.trs
     }
 
+    /// Matrix applying the transform around `pivot` instead of the origin.
+    ///
+    /// Rotation and scale happen around the pivot, which stays in place
+    /// before being translated along with everything else.
+    ///
+    /// ```
+    /// # use std::f32::consts::FRAC_PI_2;
+    /// # use glam::{vec2, vec3, Vec3};
+    /// # use inox2d::math::transform::Transform;
+    /// let mut transform = Transform::new()
+    ///     .with_translation(vec3(100., 0., 0.))
+    ///     .with_rotation(vec3(0., 0., FRAC_PI_2));
+    /// transform.update();
+    /// let matrix = transform.matrix_around(vec2(5., 5.));
+    ///
+    /// let vertex = matrix.transform_point3(vec3(10., 5., 0.));
+    /// assert!(vertex.abs_diff_eq(vec3(105., 10., 0.), 1e-4));
+    /// let pivot = matrix.transform_point3(vec3(5., 5., 0.));
+    /// assert!(pivot.abs_diff_eq(vec3(105., 5., 0.), 1e-4));
+    /// ```
+    pub fn matrix_around(&self, pivot: Vec2) -> Mat4 {
+        let pivot = pivot.extend(0.);
+        Mat4::from_translation(pivot) * self.trs * Mat4::from_translation(-pivot)
+    }
+
+    /// Makes a transform out of a matrix.
+    ///
+    /// The matrix is kept as is, translation, rotation and scale are decomposed from it.
+    /// They can't represent shearing, so calling [`Transform::update`] may change the matrix.
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+        Self {
+            trs: matrix,
+            translation,
+            rotation: Vec3::new(x, y, z),
+            scale: scale.truncate(),
+            pixel_snap: false,
+        }
+    }
+
     /// Update the internal matrix
     pub fn update(&mut self) {
         self.trs = Mat4::from_translation(self.translation)
@@ -85,6 +144,36 @@ impl Transform {
     }
 }
 
+/// Rounds the translation of a matrix mapping to puppet space to whole pixels,
+/// so that pixel art doesn't shimmer when moved by fractions of a pixel.
+pub fn snap_to_pixels(mut matrix: Mat4) -> Mat4 {
+    matrix.w_axis.x = matrix.w_axis.x.round();
+    matrix.w_axis.y = matrix.w_axis.y.round();
+    matrix
+}
+
+/// Moves `points`, each offset by the matching entry of `offsets`, with the affine 2D part of `matrix`,
+/// appending the results to `out`. Missing offsets count as zero.
+///
+/// Gives the same points as calling [`Mat4::transform_point3`] on each of them,
+/// but works on the matrix's columns in a tight loop that the compiler can vectorize.
+pub fn transform_points(matrix: Mat4, points: &[Vec2], offsets: &[Vec2], out: &mut Vec<Vec2>) {
+    let x_axis = Vec2::new(matrix.x_axis.x, matrix.x_axis.y);
+    let y_axis = Vec2::new(matrix.y_axis.x, matrix.y_axis.y);
+    let translation = Vec2::new(matrix.w_axis.x, matrix.w_axis.y);
+    let transform = |point: Vec2| x_axis * point.x + y_axis * point.y + translation;
+
+    out.reserve(points.len());
+    let (offset, rest) = points.split_at(offsets.len().min(points.len()));
+    out.extend(
+        offset
+            .iter()
+            .zip(offsets)
+            .map(|(&point, &offset)| transform(point + offset)),
+    );
+    out.extend(rest.iter().map(|&point| transform(point)));
+}
+
 impl Mul for Transform {
     type Output = Self;
 
@@ -1,14 +1,30 @@
 use std::collections::BTreeMap;
 
 use glam::{vec2, vec3, IVec2, Vec2, Vec4};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 #[derive(thiserror::Error, Debug)]
 #[error("Could not convert Vec2s to Vec<Vec2> (the array did not have an even length)")]
 pub struct Vec2sToVecVec2Error;
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-#[serde(transparent)]
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum MeshError {
+    #[error("The mesh has {vertices} vertices but {uvs} UVs")]
+    UvCountMismatch { vertices: usize, uvs: usize },
+    #[error("The mesh has {0} indices, which is not a multiple of 3")]
+    IncompleteTriangle(usize),
+    #[error("Index {index} is out of range for a mesh of {vertices} vertices")]
+    IndexOutOfRange { index: u32, vertices: usize },
+    #[error("Vertex {0} has non-finite coordinates")]
+    NonFiniteVertex(usize),
+    #[error("Triangle {0} is degenerate")]
+    DegenerateTriangle(usize),
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Vec2s(pub(crate) Vec<f32>);
 
 impl TryFrom<Vec2s> for Vec<Vec2> {
@@ -25,29 +41,34 @@ impl TryFrom<Vec2s> for Vec<Vec2> {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SMesh {
     /// Vertices in the mesh.
-    #[serde(rename = "verts")]
+    #[cfg_attr(feature = "serde", serde(rename = "verts"))]
     pub vertices: Vec2s,
     /// Base UVs.
     pub uvs: Vec2s,
     /// Indices in the mesh.
-    pub indices: Vec<u16>,
+    pub indices: Vec<u32>,
     /// Origin of the mesh.
     pub origin: Vec2,
 }
 
 /// Mesh
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
-#[serde(try_from = "SMesh", into = "SMesh")]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "SMesh", into = "SMesh"))]
 pub struct Mesh {
     /// Vertices in the mesh.
     pub vertices: Vec<Vec2>,
     /// Base UVs.
     pub uvs: Vec<Vec2>,
     /// Indices in the mesh.
-    pub indices: Vec<u16>,
+    ///
+    /// They are stored as `u32` whatever the size of the mesh,
+    /// so meshes with more than 65535 vertices don't overflow.
+    pub indices: Vec<u32>,
     /// Origin of the mesh.
     pub origin: Vec2,
 }
@@ -88,6 +109,16 @@ impl TryFrom<SMesh> for Mesh {
 }
 
 impl Mesh {
+    /// Number of triangles in the mesh, ignoring an incomplete one at the end.
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Whether the mesh has no triangle to draw, e.g. for parts only used to group others.
+    pub fn is_empty(&self) -> bool {
+        self.triangle_count() == 0
+    }
+
     /// Add a new vertex.
     pub fn add(&mut self, vertex: Vec2, uv: Vec2) {
         self.vertices.push(vertex);
@@ -100,7 +131,7 @@ impl Mesh {
     }
 
     /// Connect 2 vertices together.
-    pub fn connect(&mut self, first: u16, second: u16) {
+    pub fn connect(&mut self, first: u32, second: u32) {
         self.indices.extend([first, second]);
     }
 
@@ -145,13 +176,98 @@ impl Mesh {
         }
     }
 
+    /// Checks that the mesh can be rendered safely.
+    ///
+    /// Returns the first problem found among: mismatched vertex and UV counts,
+    /// an incomplete last triangle, out-of-range indices, non-finite vertices
+    /// and degenerate (zero-area) triangles.
+    pub fn validate(&self) -> Result<(), MeshError> {
+        if self.vertices.len() != self.uvs.len() {
+            return Err(MeshError::UvCountMismatch {
+                vertices: self.vertices.len(),
+                uvs: self.uvs.len(),
+            });
+        }
+
+        if self.indices.len() % 3 != 0 {
+            return Err(MeshError::IncompleteTriangle(self.indices.len()));
+        }
+
+        if let Some(&index) = self
+            .indices
+            .iter()
+            .find(|&&index| index as usize >= self.vertices.len())
+        {
+            return Err(MeshError::IndexOutOfRange {
+                index,
+                vertices: self.vertices.len(),
+            });
+        }
+
+        if let Some(i) = self.vertices.iter().position(|vertex| !vertex.is_finite()) {
+            return Err(MeshError::NonFiniteVertex(i));
+        }
+
+        if let Some(i) = self
+            .indices
+            .chunks_exact(3)
+            .position(|triangle| self.is_degenerate(triangle))
+        {
+            return Err(MeshError::DegenerateTriangle(i));
+        }
+
+        Ok(())
+    }
+
+    /// Makes the mesh safe to render.
+    ///
+    /// Out-of-range indices are clamped to the last vertex, then incomplete and
+    /// degenerate triangles (including those touching non-finite vertices) are dropped.
+    /// Missing UVs are filled with zeroes and extra ones are truncated.
+    ///
+    /// Returns the number of triangles that were dropped.
+    pub fn repair(&mut self) -> usize {
+        self.uvs.resize(self.vertices.len(), Vec2::ZERO);
+
+        let Some(last) = self.vertices.len().checked_sub(1) else {
+            let dropped = self.indices.len().div_ceil(3);
+            self.indices.clear();
+            return dropped;
+        };
+
+        for index in self.indices.iter_mut() {
+            *index = (*index).min(last as u32);
+        }
+
+        let num_triangles = self.indices.len().div_ceil(3);
+        let indices: Vec<u32> = self
+            .indices
+            .chunks_exact(3)
+            .filter(|triangle| !self.is_degenerate(triangle))
+            .flatten()
+            .copied()
+            .collect();
+        self.indices = indices;
+
+        num_triangles - self.indices.len() / 3
+    }
+
+    /// Whether a triangle has (close to) no area.
+    ///
+    /// Indices must be in range.
+    fn is_degenerate(&self, triangle: &[u32]) -> bool {
+        let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize]);
+        let area = (b - a).perp_dot(c - a);
+        !area.is_finite() || area.abs() <= f32::EPSILON
+    }
+
     pub fn connections_at_point(&self, point: Vec2) -> usize {
         self.find(point)
-            .map(|idx| self.connections_at_index(idx as u16))
+            .map(|idx| self.connections_at_index(idx as u32))
             .unwrap_or(0)
     }
 
-    pub fn connections_at_index(&self, index: u16) -> usize {
+    pub fn connections_at_index(&self, index: u32) -> usize {
         self.indices.iter().filter(|&idx| *idx == index).count()
     }
 
@@ -159,7 +275,7 @@ impl Mesh {
     ///
     /// # Example
     ///
-    /// ~~~no_run
+    /// ~~~ignore
     /// Mesh::quad()
     ///     // Size of texture
     ///     .size(texture.width, texture.height)
@@ -251,7 +367,7 @@ impl QuadBuilder {
                     self.uv_bounds.x + x as f32 * uvx,
                     self.uv_bounds.y + y as f32 * uvy,
                 ));
-                vert_map.insert((x, y), (vertices.len() - 1) as u16);
+                vert_map.insert((x, y), (vertices.len() - 1) as u32);
             }
         }
 
@@ -296,3 +412,62 @@ impl QuadBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_triangle_is_rejected() {
+        let mut mesh = Mesh::quad().size(8, 8).build();
+        assert_eq!(mesh.validate(), Ok(()));
+        assert!(mesh.can_triangulate());
+
+        mesh.indices.push(0);
+        let len = mesh.indices.len();
+        assert_eq!(mesh.validate(), Err(MeshError::IncompleteTriangle(len)));
+        assert!(!mesh.can_triangulate());
+
+        assert_eq!(mesh.repair(), 1);
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+
+    /// Vertices laid out on rows of 300, with a triangle using indices past `u16::MAX`.
+    const DENSE_INDICES: [u32; 3] = [0, 65_536, 69_999];
+
+    fn dense_vertices() -> Vec<Vec2> {
+        (0..70_000)
+            .map(|i| vec2((i % 300) as f32, (i / 300) as f32))
+            .collect()
+    }
+
+    #[test]
+    fn dense_mesh_indices_dont_overflow() {
+        let vertices = dense_vertices();
+        let mesh = Mesh {
+            uvs: vec![Vec2::ZERO; vertices.len()],
+            vertices,
+            indices: DENSE_INDICES.to_vec(),
+            origin: Vec2::ZERO,
+        };
+        assert_eq!(mesh.indices, DENSE_INDICES);
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn large_indices_parse_as_u32() {
+        let vertices = dense_vertices();
+        let smesh: SMesh = serde_json::from_value(serde_json::json!({
+            "verts": vertices.iter().flat_map(|v| [v.x, v.y]).collect::<Vec<_>>(),
+            "uvs": vec![0.; vertices.len() * 2],
+            "indices": DENSE_INDICES,
+            "origin": [0., 0.],
+        }))
+        .unwrap();
+        let mesh = Mesh::try_from(smesh).unwrap();
+        assert_eq!(mesh.vertices, vertices);
+        assert_eq!(mesh.indices, DENSE_INDICES);
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+}
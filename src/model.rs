@@ -1,13 +1,616 @@
-use crate::puppet::Puppet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
-#[derive(Clone, Debug)]
+#[cfg(feature = "serde")]
+use crate::hash::Fnv1a;
+use crate::mesh::MeshError;
+use crate::nodes::composite::Composite;
+use crate::nodes::drawable::BlendMode;
+use crate::nodes::drivers::simple_physics::SimplePhysics;
+use crate::nodes::node::NodeUuid;
+use crate::nodes::part::Part;
+use crate::params::ParamUuid;
+use crate::puppet::{Puppet, RenderHints};
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct ModelTexture {
     pub format: image::ImageFormat,
     pub data: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl ModelTexture {
+    /// CRC-32 of the texture's data, as stored in the file, to check that it wasn't corrupted in transit.
+    ///
+    /// This doesn't protect against tampering, only against accidental corruption.
+    pub fn checksum(&self) -> u32 {
+        crc32fast::hash(&self.data)
+    }
+
+    fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.format.hash(&mut hasher);
+        self.data.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// What a part uses a texture for, given by its slot in [`Part::textures`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TextureRole {
+    Albedo,
+    Emissive,
+    Bump,
+}
+
+impl TextureRole {
+    /// Roles of the texture slots of a part, in order.
+    pub const SLOTS: [TextureRole; 3] = [
+        TextureRole::Albedo,
+        TextureRole::Emissive,
+        TextureRole::Bump,
+    ];
+}
+
+/// Textures shared between several models, so that identical ones are only kept once in memory.
+///
+/// See [`parse_inp_into`](crate::parsers::inp::parse_inp_into).
+#[derive(Debug, Default)]
+pub struct TextureStore {
+    textures: HashMap<u64, Vec<Arc<ModelTexture>>>,
+}
+
+impl TextureStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a texture to the store, returning a handle to it.
+    ///
+    /// If the store already has a texture with the same contents, a handle to that one is returned instead.
+    pub fn insert(&mut self, texture: ModelTexture) -> Arc<ModelTexture> {
+        let bucket = self.textures.entry(texture.content_hash()).or_default();
+        if let Some(existing) = bucket
+            .iter()
+            .find(|existing| existing.format == texture.format && existing.data == texture.data)
+        {
+            return existing.clone();
+        }
+
+        let texture = Arc::new(texture);
+        bucket.push(texture.clone());
+        texture
+    }
+
+    /// Number of distinct textures in the store.
+    pub fn len(&self) -> usize {
+        self.textures.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// Size of all the textures in the store, as stored in the files.
+    pub fn total_bytes(&self) -> usize {
+        self.textures
+            .values()
+            .flatten()
+            .map(|texture| texture.data.len())
+            .sum()
+    }
+
+    /// Drops the textures that aren't used by any model anymore.
+    pub fn purge_unused(&mut self) {
+        for bucket in self.textures.values_mut() {
+            bucket.retain(|texture| Arc::strong_count(texture) > 1);
+        }
+        self.textures.retain(|_, bucket| !bucket.is_empty());
+    }
+}
+
+/// Model whose textures live in a [`TextureStore`].
+///
+/// Parts refer to textures by their index in `textures`, like in [`Model`].
+#[derive(Debug, PartialEq)]
+pub struct SharedModel {
+    pub puppet: Puppet,
+    pub textures: Vec<Arc<ModelTexture>>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    #[error("Part {part:?} has an invalid mesh: {error}")]
+    InvalidMesh { part: NodeUuid, error: MeshError },
+    #[error("Part {part:?} uses texture {texture}, but the model only has {num_textures}")]
+    MissingTexture {
+        part: NodeUuid,
+        texture: usize,
+        num_textures: usize,
+    },
+    #[error("Binding {binding} of param {param:?} on node {node:?} has a {found:?} keypoint grid, its axis points make a {expected:?} one")]
+    BindingGridMismatch {
+        param: ParamUuid,
+        /// Index of the binding in [`Param::bindings`](crate::params::Param::bindings).
+        binding: usize,
+        node: NodeUuid,
+        /// Number of X and Y axis points of the param.
+        expected: (usize, usize),
+        /// Number of columns of the binding, and length of the first one that doesn't match, if any.
+        found: (usize, usize),
+    },
+}
+
+/// Summary of a model's contents, see [`Model::report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelReport {
+    pub node_count: usize,
+    pub part_count: usize,
+    pub param_count: usize,
+    pub texture_count: usize,
+    /// Size of all the textures, as stored in the file.
+    pub total_texture_bytes: usize,
+    /// Blend modes of all the parts and composites.
+    pub blend_modes_used: BTreeSet<BlendMode>,
+    pub physics_node_count: usize,
+    /// Depth of the deepest node, the root having a depth of 0.
+    pub max_tree_depth: usize,
+}
+
+/// What a texture of a model is and what uses it, see [`Model::texture_infos`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextureInfo {
+    pub index: usize,
+    pub format: image::ImageFormat,
+    /// Size of the texture as stored in the file.
+    pub compressed_bytes: usize,
+    /// See [`Model::texture_roles`].
+    pub role: Option<TextureRole>,
+    /// Parts using the texture, sorted.
+    pub referenced_by: Vec<NodeUuid>,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Model {
     pub puppet: Puppet,
     pub textures: Vec<ModelTexture>,
 }
+
+/// Role of each of the `count` textures of a puppet, see [`Model::texture_roles`].
+pub(crate) fn texture_roles(puppet: &Puppet, count: usize) -> Vec<Option<TextureRole>> {
+    let mut roles = vec![None; count];
+    let parts = puppet
+        .nodes
+        .iter()
+        .filter_map(|(_, node)| node.as_any().downcast_ref::<Part>());
+    for part in parts {
+        for (&texture, role) in part.textures.iter().zip(TextureRole::SLOTS) {
+            if let Some(slot) = roles.get_mut(texture) {
+                *slot = Some(slot.map_or(role, |existing: TextureRole| existing.min(role)));
+            }
+        }
+    }
+    roles
+}
+
+impl Model {
+    /// Whether the model has any texture, which isn't the case for armature-only puppets.
+    pub fn has_textures(&self) -> bool {
+        !self.textures.is_empty()
+    }
+
+    /// Version of the Inochi2D spec the puppet targets, as stored in the file, e.g. `"1.0-alpha"`.
+    ///
+    /// See [`PuppetMeta::spec_version`](crate::puppet::PuppetMeta::spec_version) to compare it.
+    pub fn format_version(&self) -> &str {
+        &self.puppet.meta.version
+    }
+
+    /// Version of Inochi Creator the puppet was last saved with, if it recorded it.
+    pub fn creator_version(&self) -> Option<&str> {
+        self.puppet.meta.creator_version.as_deref()
+    }
+
+    /// How the creator of the puppet intended it to be rendered.
+    pub fn render_hints(&self) -> &RenderHints {
+        &self.puppet.meta.render_hints
+    }
+
+    fn parts(&self) -> impl Iterator<Item = &Part> {
+        self.puppet
+            .nodes
+            .iter()
+            .filter_map(|(_, node)| node.as_any().downcast_ref::<Part>())
+    }
+
+    /// Role of each texture, or `None` for the textures no part uses.
+    ///
+    /// A texture used for several roles gets the first one in [`TextureRole::SLOTS`] order.
+    pub fn texture_roles(&self) -> Vec<Option<TextureRole>> {
+        texture_roles(&self.puppet, self.textures.len())
+    }
+
+    /// Describes every texture of the model, in order.
+    pub fn texture_infos(&self) -> Vec<TextureInfo> {
+        let mut infos: Vec<_> = self
+            .textures
+            .iter()
+            .zip(self.texture_roles())
+            .enumerate()
+            .map(|(index, (texture, role))| TextureInfo {
+                index,
+                format: texture.format,
+                compressed_bytes: texture.data.len(),
+                role,
+                referenced_by: Vec::new(),
+            })
+            .collect();
+
+        for part in self.parts() {
+            for &texture in &part.textures {
+                if let Some(info) = infos.get_mut(texture) {
+                    info.referenced_by.push(part.node_state.uuid);
+                }
+            }
+        }
+        for info in &mut infos {
+            info.referenced_by.sort();
+            info.referenced_by.dedup();
+        }
+        infos
+    }
+
+    /// Compares the [checksums](ModelTexture::checksum) of the textures with the `expected` ones, in order,
+    /// returning the indices of the textures that don't match, e.g. to fetch them again.
+    ///
+    /// Textures without an expected checksum, and expected checksums without a texture, are mismatches.
+    pub fn verify_textures(&self, expected: &[u32]) -> Vec<usize> {
+        (0..self.textures.len().max(expected.len()))
+            .filter(|&i| {
+                self.textures.get(i).map(ModelTexture::checksum) != expected.get(i).copied()
+            })
+            .collect()
+    }
+
+    /// Hash of the contents of the model, e.g. to cache resources made out of it across reloads.
+    ///
+    /// It covers everything that is saved in the file: metadata, nodes, their place in the tree,
+    /// parameters, animations and the bytes of the textures, but not the current pose.
+    /// Nodes and parameters are hashed in UUID order, so the hash is the same for every load of a file
+    /// and for every run of the program. It identifies contents, not puppets:
+    /// any edit gives a new hash, and identical copies of a puppet share one.
+    #[cfg(feature = "serde")]
+    pub fn content_hash(&self) -> u64 {
+        let puppet = &self.puppet;
+        let mut hasher = Fnv1a::new();
+        hasher.write_json(&puppet.meta);
+        hasher.write_json(&puppet.physics);
+
+        for uuid in puppet.nodes.all_uuids() {
+            let Some(node) = puppet.nodes.get_node(uuid) else {
+                continue;
+            };
+            hasher.write_json(node);
+            let children = puppet.nodes.get_children_uuids(uuid).unwrap_or_default();
+            hasher.write_bytes(&(children.len() as u64).to_le_bytes());
+            for child in children {
+                hasher.write_bytes(&child.as_u32().to_le_bytes());
+            }
+        }
+
+        let mut params: Vec<_> = puppet.parameters.iter().collect();
+        params.sort_by_key(|param| param.uuid);
+        hasher.write_json(&params);
+        hasher.write_json(&puppet.automation);
+        hasher.write_json(&puppet.animations);
+
+        for texture in &self.textures {
+            hasher.write_field(texture.format.extensions_str()[0].as_bytes());
+            hasher.write_field(&texture.data);
+        }
+        hasher.finish()
+    }
+
+    /// Summarizes the contents of the model.
+    pub fn report(&self) -> ModelReport {
+        let nodes = &self.puppet.nodes;
+        let mut report = ModelReport {
+            node_count: 0,
+            part_count: 0,
+            param_count: self.puppet.parameters.len(),
+            texture_count: self.textures.len(),
+            total_texture_bytes: self.textures.iter().map(|tex| tex.data.len()).sum(),
+            blend_modes_used: BTreeSet::new(),
+            physics_node_count: 0,
+            max_tree_depth: 0,
+        };
+
+        for (uuid, node) in nodes.iter() {
+            report.node_count += 1;
+
+            let node = node.as_any();
+            if let Some(part) = node.downcast_ref::<Part>() {
+                report.part_count += 1;
+                report.blend_modes_used.insert(part.draw_state.blend_mode);
+            } else if let Some(composite) = node.downcast_ref::<Composite>() {
+                report
+                    .blend_modes_used
+                    .insert(composite.draw_state.blend_mode);
+            } else if node.is::<SimplePhysics>() {
+                report.physics_node_count += 1;
+            }
+
+            let depth = nodes.ancestors(uuid).count() - 1;
+            report.max_tree_depth = report.max_tree_depth.max(depth);
+        }
+
+        report
+    }
+
+    /// Checks that every part of the model can be rendered safely,
+    /// and that the keypoint grid of every binding matches the axis points of its param.
+    ///
+    /// Returns all the problems that were found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        for part in self.parts() {
+            let uuid = part.node_state.uuid;
+
+            if let Err(error) = part.mesh.validate() {
+                errors.push(ValidationError::InvalidMesh { part: uuid, error });
+            }
+
+            for &texture in &part.textures {
+                if texture >= self.textures.len() {
+                    errors.push(ValidationError::MissingTexture {
+                        part: uuid,
+                        texture,
+                        num_textures: self.textures.len(),
+                    });
+                }
+            }
+        }
+
+        for param in &self.puppet.parameters {
+            let expected = (param.axis_points[0].len(), param.axis_points[1].len());
+            for (i, binding) in param.bindings.iter().enumerate() {
+                let columns = binding.grid_columns();
+                let rows = columns
+                    .iter()
+                    .copied()
+                    .find(|&rows| rows != expected.1)
+                    .unwrap_or(expected.1);
+                let found = (columns.len(), rows);
+                if found != expected {
+                    errors.push(ValidationError::BindingGridMismatch {
+                        param: param.uuid,
+                        binding: i,
+                        node: binding.node(),
+                        expected,
+                        found,
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Repairs the meshes of every part, see [`Mesh::repair`](crate::mesh::Mesh::repair).
+    ///
+    /// Returns the total number of triangles that were dropped.
+    pub fn repair_meshes(&mut self) -> usize {
+        self.puppet
+            .nodes
+            .iter_mut()
+            .filter_map(|(_, node)| node.as_any_mut().downcast_mut::<Part>())
+            .map(|part| part.mesh.repair())
+            .sum()
+    }
+
+    /// Makes a new model out of a node and its descendants, see [`Puppet::extract_subtree`].
+    ///
+    /// Only the textures used by the extracted parts are kept, and the parts are updated to match.
+    /// The puppet's thumbnail is dropped.
+    pub fn extract_subtree(&self, uuid: NodeUuid) -> Option<Model> {
+        let mut puppet = self.puppet.extract_subtree(uuid)?;
+        puppet.meta.thumbnail_id = None;
+
+        let mut remap = BTreeMap::new();
+        let mut textures = Vec::new();
+        for (_, node) in puppet.nodes.iter_mut() {
+            let Some(part) = node.as_any_mut().downcast_mut::<Part>() else {
+                continue;
+            };
+            for index in &mut part.textures {
+                let Some(texture) = self.textures.get(*index) else {
+                    continue;
+                };
+                *index = *remap.entry(*index).or_insert_with(|| {
+                    textures.push(texture.clone());
+                    textures.len() - 1
+                });
+            }
+        }
+
+        Some(Model { puppet, textures })
+    }
+
+    /// Merges textures with identical contents, and points the parts to the remaining copy.
+    ///
+    /// Texture indices of parts that are out of range are left as is.
+    /// Returns the number of bytes saved.
+    pub fn dedup_textures(&mut self) -> usize {
+        let mut canonical = HashMap::new();
+        let mut remap = Vec::with_capacity(self.textures.len());
+        let mut kept = Vec::new();
+        for (i, texture) in self.textures.iter().enumerate() {
+            let next = kept.len();
+            let new_index = *canonical
+                .entry((texture.format, texture.data.as_slice()))
+                .or_insert(next);
+            if new_index == next {
+                kept.push(i);
+            }
+            remap.push(new_index);
+        }
+
+        if kept.len() == self.textures.len() {
+            return 0;
+        }
+
+        for (_, node) in self.puppet.nodes.iter_mut() {
+            if let Some(part) = node.as_any_mut().downcast_mut::<Part>() {
+                for texture in &mut part.textures {
+                    if let Some(&new_index) = remap.get(*texture) {
+                        *texture = new_index;
+                    }
+                }
+            }
+        }
+
+        let mut saved = 0;
+        let mut i = 0;
+        self.textures.retain(|texture| {
+            let keep = kept.binary_search(&i).is_ok();
+            if !keep {
+                saved += texture.data.len();
+            }
+            i += 1;
+            keep
+        });
+        saved
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use glam::Vec2;
+    use image::ImageFormat;
+
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::mesh::Mesh;
+    use crate::nodes::node::NodeState;
+    use crate::params::{Binding, BindingBase, InterpolateMode, MergeMode, Param};
+
+    fn part(uuid: u32, textures: [usize; 3]) -> serde_json::Value {
+        serde_json::json!({
+            "type": "Part",
+            "uuid": uuid,
+            "name": format!("Part {uuid}"),
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "blend_mode": "Normal",
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+            "opacity": 1.,
+            "mesh": { "verts": [], "uvs": [], "indices": [], "origin": [0., 0.] },
+            "textures": textures,
+        })
+    }
+
+    fn model(parts: Vec<serde_json::Value>, textures: &[&[u8]]) -> Model {
+        let puppet = serde_json::from_value(serde_json::json!({
+            "meta": { "version": "1.0-alpha" },
+            "physics": { "pixelsPerMeter": 1000., "gravity": 9.8 },
+            "nodes": {
+                "type": "Node",
+                "uuid": 0,
+                "name": "Root",
+                "enabled": true,
+                "zsort": 0.,
+                "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+                "lockToRoot": false,
+                "children": parts,
+            },
+            "param": [],
+        }))
+        .unwrap();
+        let textures = textures
+            .iter()
+            .map(|data| ModelTexture {
+                format: ImageFormat::Png,
+                data: data.to_vec(),
+            })
+            .collect();
+        Model { puppet, textures }
+    }
+
+    fn part_textures(model: &Model, uuid: u32) -> [usize; 3] {
+        let node = model.puppet.nodes.get_node(NodeUuid(uuid)).unwrap();
+        node.as_any().downcast_ref::<Part>().unwrap().textures
+    }
+
+    #[test]
+    fn dedup_remaps_every_texture_slot() {
+        let albedo: &[u8] = b"albedo";
+        let emissive: &[u8] = b"emissive";
+        let bump: &[u8] = b"bump";
+        // Albedo and emissive are stored twice
+        let mut model = model(
+            vec![part(1, [0, 1, 2]), part(2, [3, 4, 0]), part(3, [4, 4, 3])],
+            &[albedo, emissive, bump, albedo, emissive],
+        );
+
+        assert_eq!(model.dedup_textures(), albedo.len() + emissive.len());
+
+        let textures: Vec<_> = model.textures.iter().map(|t| t.data.as_slice()).collect();
+        assert_eq!(textures, [albedo, emissive, bump]);
+        assert_eq!(part_textures(&model, 1), [0, 1, 2]);
+        assert_eq!(part_textures(&model, 2), [0, 1, 0]);
+        assert_eq!(part_textures(&model, 3), [1, 1, 0]);
+        assert_eq!(model.dedup_textures(), 0);
+    }
+
+    #[test]
+    fn short_binding_values_are_reported() {
+        let root = NodeUuid(1);
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder.add_texture(ModelTexture {
+            format: ImageFormat::Png,
+            data: Vec::new(),
+        });
+        let mesh = Mesh::quad().size(8, 8).build();
+        let deform = vec![Vec2::ONE; mesh.vertices.len()];
+        builder
+            .add_part(root, NodeState::new(NodeUuid(2), "Part"), mesh, [0; 3])
+            .unwrap();
+
+        let mut param = Param::new(ParamUuid(1), "Param", true, Vec2::ZERO, Vec2::ONE);
+        // The second column is missing a keypoint of the 2x2 grid
+        param.bindings.push(Binding::Deform {
+            base: BindingBase {
+                node: NodeUuid(2),
+                is_set: vec![vec![true; 2]; 2],
+                interpolate_mode: InterpolateMode::Linear,
+                merge_mode: MergeMode::Additive,
+            },
+            values: vec![vec![deform.clone(), deform.clone()], vec![deform]],
+        });
+        builder.add_param(param).unwrap();
+        let mut model = builder.build();
+
+        assert_eq!(
+            model.validate(),
+            Err(vec![ValidationError::BindingGridMismatch {
+                param: ParamUuid(1),
+                binding: 0,
+                node: NodeUuid(2),
+                expected: (2, 2),
+                found: (2, 1),
+            }])
+        );
+        // Posing the corrupt binding doesn't panic either
+        model.puppet.set_param(ParamUuid(1), Vec2::ONE);
+    }
+}
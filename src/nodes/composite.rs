@@ -1,16 +1,77 @@
 use crate::impl_node;
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use super::drawable::Drawable;
+use super::drawable::{DrawState, Drawable};
 use super::node::NodeState;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg(feature = "serde")]
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+#[cfg(feature = "serde")]
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Composite {
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub(crate) node_state: NodeState,
-    #[serde(flatten)]
-    pub(crate) draw_state: Drawable,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub(crate) draw_state: DrawState,
+    /// Whether the masks of this composite also apply to the composites nested in it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_true",
+            rename = "propagateMeshGroup",
+            skip_serializing_if = "is_true"
+        )
+    )]
+    pub(crate) propagate_mesh_group: bool,
+    /// Whether this composite ignores the masks of the composites it is nested in.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, rename = "doNotMask", skip_serializing_if = "is_false")
+    )]
+    pub(crate) do_not_mask: bool,
 }
 
-impl_node!(Composite, node_state);
\ No newline at end of file
+impl_node!(Composite, node_state, Composite, {
+    fn as_drawable(&self) -> Option<&dyn Drawable> {
+        Some(self)
+    }
+});
+
+impl Drawable for Composite {
+    fn draw_state(&self) -> &DrawState {
+        &self.draw_state
+    }
+}
+
+impl Composite {
+    /// Alpha cutoff used by the stencil comparison when this composite is masked.
+    pub fn threshold(&self) -> f32 {
+        self.draw_state.mask_threshold
+    }
+
+    /// Whether composites nested in this one are masked by its masks too.
+    pub fn propagates_masks(&self) -> bool {
+        self.propagate_mesh_group
+    }
+
+    /// Whether this composite is masked by the masks of the composites it is nested in,
+    /// provided they propagate them.
+    pub fn inherits_masks(&self) -> bool {
+        !self.do_not_mask
+    }
+}
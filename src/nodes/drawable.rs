@@ -1,10 +1,14 @@
 use glam::Vec3;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use super::node::NodeUuid;
+use crate::mesh::Mesh;
+
+use super::node::{Node, NodeUuid};
 
 /// Blending modes
-#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BlendMode {
     /// Normal blending mode.
     Normal,
@@ -27,26 +31,189 @@ pub enum BlendMode {
     SliceFromLower,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
-enum MaskMode {
+/// Masking modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MaskMode {
+    /// The part should be masked by the drawables specified.
     Mask,
+    /// The part should be dodge-masked by the drawables specified.
+    DodgeMask,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-#[serde(deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Mask {
     pub source: NodeUuid,
-    mode: MaskMode,
+    pub mode: MaskMode,
+    /// Alpha threshold overriding the drawable's `mask_threshold` for this mask only.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub threshold: Option<f32>,
+}
+
+/// Mask of a drawable along with everything needed to render it, see [`NodeTree::masks_of`](super::node_tree::NodeTree::masks_of).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedMask {
+    pub source: NodeUuid,
+    pub mode: MaskMode,
+    /// Alpha threshold of the mask, see [`DrawState::threshold_for`].
+    pub threshold: f32,
+    /// Whether the puppet has a node with the UUID of the source.
+    /// Masks whose source is missing have nothing to render into the stencil buffer.
+    pub source_node_exists: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Drawable {
+/// Welds vertices of a drawable to the vertices of a neighbouring one,
+/// so that seams between them don't tear when deformed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct WeldedLink {
+    /// Drawable the vertices are welded to.
+    pub target: NodeUuid,
+    /// For each vertex of this drawable, index of the vertex of `target` it is welded to,
+    /// or -1 if it's not welded.
+    pub indices: Vec<isize>,
+    /// How strongly the vertices are pulled towards the target, from 0 to 1.
+    pub weight: f32,
+}
+
+impl WeldedLink {
+    /// Pairs of welded vertices, as (index in this drawable, index in the target).
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.indices
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &target)| Some((i, usize::try_from(target).ok()?)))
+    }
+}
+
+/// How a drawable node is drawn.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DrawState {
     pub blend_mode: BlendMode,
     pub tint: Vec3,
-    #[serde(rename = "screenTint")]
+    #[cfg_attr(feature = "serde", serde(rename = "screenTint"))]
     pub screen_tint: Vec3,
     pub mask_threshold: f32,
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
     pub masks: Vec<Mask>,
     pub opacity: f32,
+    /// Factor applied to `opacity` by parameter bindings.
+    #[cfg_attr(feature = "serde", serde(skip, default = "one"))]
+    pub offset_opacity: f32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, rename = "weldedLinks", skip_serializing_if = "Vec::is_empty")
+    )]
+    pub welded_links: Vec<WeldedLink>,
+    /// Group the drawable is shown and hidden with, e.g. an outfit,
+    /// see [`Puppet::toggle_group`](crate::puppet::Puppet::toggle_group).
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, rename = "groupId", skip_serializing_if = "Option::is_none")
+    )]
+    pub group_id: Option<String>,
+}
+
+/// Opaque, untinted and normally blended, like new drawables in Inochi Creator.
+impl Default for DrawState {
+    fn default() -> Self {
+        Self {
+            blend_mode: BlendMode::Normal,
+            tint: Vec3::ONE,
+            screen_tint: Vec3::ZERO,
+            mask_threshold: 0.5,
+            masks: Vec::new(),
+            opacity: 1.,
+            offset_opacity: 1.,
+            welded_links: Vec::new(),
+            group_id: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn one() -> f32 {
+    1.
+}
+
+impl DrawState {
+    /// Opacity of the drawable itself, with binding offsets applied.
+    pub fn local_opacity(&self) -> f32 {
+        self.opacity * self.offset_opacity
+    }
+
+    /// Alpha cutoff to use when rendering `mask` into the stencil buffer.
+    ///
+    /// Texels of the mask source with an alpha at or below this value are discarded.
+    pub fn threshold_for(&self, mask: &Mask) -> f32 {
+        mask.threshold.unwrap_or(self.mask_threshold).clamp(0., 1.)
+    }
+}
+
+/// Node that gets drawn, i.e. a [`Part`](super::part::Part) or a [`Composite`](super::composite::Composite),
+/// for renderers to handle both without downcasting. See [`Node::as_drawable`].
+pub trait Drawable: Node {
+    fn draw_state(&self) -> &DrawState;
+
+    /// Mesh the drawable is drawn with, `None` for composites, which draw their children instead.
+    fn mesh(&self) -> Option<&Mesh> {
+        None
+    }
+
+    /// Indices of the textures the drawable samples,
+    /// in [`TextureRole::SLOTS`](crate::model::TextureRole::SLOTS) order.
+    fn texture_indices(&self) -> &[usize] {
+        &[]
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        self.draw_state().blend_mode
+    }
+
+    /// Opacity of the drawable itself, see [`DrawState::local_opacity`].
+    fn opacity(&self) -> f32 {
+        self.draw_state().local_opacity()
+    }
+
+    fn masks(&self) -> &[Mask] {
+        &self.draw_state().masks
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_threshold_falls_back_to_the_drawable() {
+        let drawable: DrawState = serde_json::from_value(serde_json::json!({
+            "blend_mode": "Normal",
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+            "masks": [
+                { "source": 1, "mode": "Mask" },
+                { "source": 2, "mode": "DodgeMask", "threshold": 0.2 },
+                { "source": 3, "mode": "Mask", "threshold": 1.5 },
+            ],
+            "opacity": 1.,
+        }))
+        .unwrap();
+
+        let thresholds: Vec<_> = drawable
+            .masks
+            .iter()
+            .map(|mask| drawable.threshold_for(mask))
+            .collect();
+        assert_eq!(thresholds, [0.5, 0.2, 1.]);
+    }
 }
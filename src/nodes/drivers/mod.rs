@@ -1 +1,288 @@
+use std::collections::BTreeSet;
+
+use crate::nodes::node::NodeUuid;
+use crate::nodes::node_tree::NodeTree;
+use crate::params::{Binding, Param, ParamUuid};
+
+use self::simple_physics::SimplePhysics;
+
 pub mod simple_physics;
+
+/// Physics nodes that end up driving themselves through their output parameters,
+/// see [`Puppet::validate_driver_graph`](crate::puppet::Puppet::validate_driver_graph).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    /// The physics nodes, by UUID.
+    pub nodes: Vec<NodeUuid>,
+    /// Output parameter of each of the nodes.
+    pub params: Vec<ParamUuid>,
+}
+
+/// Which physics nodes move which through their output parameters.
+///
+/// A physics node drives another when its parameter has a binding to the other node or one of its ancestors,
+/// as that moves the other node's anchor.
+pub(crate) struct DriverGraph {
+    /// Physics nodes by UUID, with their output parameter.
+    nodes: Vec<(NodeUuid, ParamUuid)>,
+    /// Indices of the nodes each node drives.
+    edges: Vec<Vec<usize>>,
+}
+
+impl DriverGraph {
+    pub(crate) fn new(tree: &NodeTree, params: &[Param]) -> Self {
+        let mut nodes: Vec<_> = tree
+            .iter()
+            .filter_map(|(uuid, node)| {
+                let physics = node.as_any().downcast_ref::<SimplePhysics>()?;
+                Some((uuid, physics.param()))
+            })
+            .collect();
+        nodes.sort_unstable();
+
+        let moved_by: Vec<BTreeSet<NodeUuid>> = nodes
+            .iter()
+            .map(|&(uuid, _)| {
+                tree.ancestors(uuid)
+                    .filter_map(|ancestor| tree.arena.get(ancestor))
+                    .map(|ancestor| ancestor.get().get_node_state().uuid)
+                    .collect()
+            })
+            .collect();
+
+        let edges = nodes
+            .iter()
+            .map(|&(_, output)| {
+                let Some(param) = params.iter().find(|param| param.uuid == output) else {
+                    return Vec::new();
+                };
+                let driven: BTreeSet<_> = param.bindings.iter().map(Binding::node).collect();
+                (0..nodes.len())
+                    .filter(|&i| !moved_by[i].is_disjoint(&driven))
+                    .collect()
+            })
+            .collect();
+
+        Self { nodes, edges }
+    }
+
+    /// Physics nodes in an order where each comes after the ones driving it.
+    ///
+    /// Nodes that are part of a cycle, or driven by one, come last by UUID.
+    pub(crate) fn order(&self) -> Vec<NodeUuid> {
+        let mut in_degree = vec![0; self.nodes.len()];
+        for &to in self.edges.iter().flatten() {
+            in_degree[to] += 1;
+        }
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut done = vec![false; self.nodes.len()];
+        let mut ready: Vec<_> = (0..self.nodes.len())
+            .filter(|&i| in_degree[i] == 0)
+            .rev()
+            .collect();
+        while let Some(i) = ready.pop() {
+            done[i] = true;
+            order.push(self.nodes[i].0);
+            for &to in self.edges[i].iter().rev() {
+                in_degree[to] -= 1;
+                if in_degree[to] == 0 {
+                    ready.push(to);
+                }
+            }
+        }
+
+        order.extend(
+            (0..self.nodes.len())
+                .filter(|&i| !done[i])
+                .map(|i| self.nodes[i].0),
+        );
+        order
+    }
+
+    /// Groups of physics nodes that drive each other, found with Tarjan's algorithm.
+    pub(crate) fn cycles(&self) -> Vec<Cycle> {
+        struct Tarjan<'a> {
+            edges: &'a [Vec<usize>],
+            index: Vec<Option<usize>>,
+            low_link: Vec<usize>,
+            stack: Vec<usize>,
+            on_stack: Vec<bool>,
+            next_index: usize,
+            components: Vec<Vec<usize>>,
+        }
+
+        impl Tarjan<'_> {
+            fn visit(&mut self, i: usize) {
+                self.index[i] = Some(self.next_index);
+                self.low_link[i] = self.next_index;
+                self.next_index += 1;
+                self.stack.push(i);
+                self.on_stack[i] = true;
+
+                for &to in &self.edges[i] {
+                    match self.index[to] {
+                        None => {
+                            self.visit(to);
+                            self.low_link[i] = self.low_link[i].min(self.low_link[to]);
+                        }
+                        Some(index) if self.on_stack[to] => {
+                            self.low_link[i] = self.low_link[i].min(index);
+                        }
+                        Some(_) => {}
+                    }
+                }
+
+                if Some(self.low_link[i]) == self.index[i] {
+                    let mut component = Vec::new();
+                    while let Some(j) = self.stack.pop() {
+                        self.on_stack[j] = false;
+                        component.push(j);
+                        if j == i {
+                            break;
+                        }
+                    }
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let len = self.nodes.len();
+        let mut tarjan = Tarjan {
+            edges: &self.edges,
+            index: vec![None; len],
+            low_link: vec![0; len],
+            stack: Vec::new(),
+            on_stack: vec![false; len],
+            next_index: 0,
+            components: Vec::new(),
+        };
+        for i in 0..len {
+            if tarjan.index[i].is_none() {
+                tarjan.visit(i);
+            }
+        }
+
+        let mut cycles: Vec<_> = tarjan
+            .components
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.edges[component[0]].contains(&component[0])
+            })
+            .map(|mut component| {
+                component.sort_unstable();
+                Cycle {
+                    nodes: component.iter().map(|&i| self.nodes[i].0).collect(),
+                    params: component.iter().map(|&i| self.nodes[i].1).collect(),
+                }
+            })
+            .collect();
+        cycles.sort_by_key(|cycle| cycle.nodes.first().copied());
+        cycles
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::model::Model;
+    use crate::nodes::node::NodeState;
+    use crate::params::{BindingBase, InterpolateMode, MergeMode};
+
+    fn physics(uuid: u32, param: u32) -> SimplePhysics {
+        serde_json::from_value(serde_json::json!({
+            "uuid": uuid,
+            "name": format!("Physics {uuid}"),
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "param": param,
+            "model_type": "Pendulum",
+            "map_mode": "AngleLength",
+            "gravity": 1.,
+            "length": 100.,
+            "frequency": 1.,
+            "angle_damping": 0.5,
+            "length_damping": 0.5,
+            "output_scale": [1., 1.],
+        }))
+        .unwrap()
+    }
+
+    /// Parameter moving each of `nodes` along X.
+    fn output(uuid: u32, nodes: &[u32]) -> Param {
+        let mut param = Param::new(
+            ParamUuid(uuid),
+            "Output",
+            false,
+            vec2(-1., 0.),
+            vec2(1., 0.),
+        );
+        param.bindings = nodes
+            .iter()
+            .map(|&node| Binding::TransformTX {
+                base: BindingBase {
+                    node: NodeUuid(node),
+                    is_set: vec![vec![true], vec![true]],
+                    interpolate_mode: InterpolateMode::Linear,
+                    merge_mode: MergeMode::Additive,
+                },
+                values: vec![vec![-5.], vec![5.]],
+            })
+            .collect();
+        param
+    }
+
+    /// Plain nodes then physics nodes as `(uuid, parent)`, under a root with UUID 0.
+    ///
+    /// The physics node with UUID `n` outputs to the parameter with UUID `10 * n`.
+    fn model(groups: &[(u32, u32)], nodes: &[(u32, u32)], params: Vec<Param>) -> Model {
+        let mut builder = ModelBuilder::new(NodeState::new(NodeUuid(0), "Root"));
+        for &(uuid, parent) in groups {
+            let group = NodeState::new(NodeUuid(uuid), format!("Group {uuid}"));
+            builder.add_node(NodeUuid(parent), group).unwrap();
+        }
+        for &(uuid, parent) in nodes {
+            builder
+                .add_node(NodeUuid(parent), physics(uuid, 10 * uuid))
+                .unwrap();
+        }
+        for param in params {
+            builder.add_param(param).unwrap();
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn nodes_driving_each_other_are_a_cycle() {
+        // 1 and 2 move each other, 1 also moves 3, and 4 moves nothing
+        let params = vec![output(10, &[2, 3]), output(20, &[1])];
+        let model = model(&[], &[(1, 0), (2, 0), (3, 0), (4, 0)], params);
+        let puppet = &model.puppet;
+
+        let cycle = Cycle {
+            nodes: vec![NodeUuid(1), NodeUuid(2)],
+            params: vec![ParamUuid(10), ParamUuid(20)],
+        };
+        assert_eq!(puppet.validate_driver_graph(), Err(vec![cycle]));
+        // The cycle and what it drives go last
+        let order = DriverGraph::new(&puppet.nodes, &puppet.parameters).order();
+        assert_eq!(order, [4, 1, 2, 3].map(NodeUuid));
+    }
+
+    #[test]
+    fn driven_nodes_are_simulated_after_their_drivers() {
+        // 3 moves the group 5 that 2 hangs from, and 2 moves 1
+        let params = vec![output(20, &[1]), output(30, &[5])];
+        let model = model(&[(5, 0)], &[(1, 0), (2, 5), (3, 0)], params);
+        let puppet = &model.puppet;
+
+        assert_eq!(puppet.validate_driver_graph(), Ok(()));
+        let order = DriverGraph::new(&puppet.nodes, &puppet.parameters).order();
+        assert_eq!(order, [3, 2, 1].map(NodeUuid));
+    }
+}
@@ -1,12 +1,24 @@
-use glam::Vec2;
+use std::f32::consts::PI;
+
+use glam::{vec2, Vec2};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::impl_node;
 use crate::nodes::node::NodeState;
+use crate::params::ParamUuid;
+use crate::puppet::PuppetPhysics;
+
+/// Longest step the simulation advances by, longer frames are split into several steps.
+const MAX_STEP: f32 = 1. / 120.;
+/// Shortest frame the simulation advances by, shorter ones are stretched to it
+/// so that the bob doesn't move by amounts lost to rounding.
+const MIN_FRAME: f32 = 1e-5;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SimplePhysics {
-    #[serde(flatten)]
+    #[cfg_attr(feature = "serde", serde(flatten))]
     node_state: NodeState,
     param: u32,
     model_type: String,
@@ -19,4 +31,236 @@ pub struct SimplePhysics {
     output_scale: Vec2,
 }
 
-impl_node!(SimplePhysics, node_state);
\ No newline at end of file
+impl_node!(SimplePhysics, node_state, SimplePhysics);
+
+/// Simulation state of a [`SimplePhysics`] node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PendulumState {
+    /// Position of the bob in puppet space.
+    bob: Vec2,
+    /// Velocity of the bob, only used by spring pendulums.
+    velocity: Vec2,
+    /// Angular velocity of the bob, only used by rigid pendulums.
+    angular_velocity: f32,
+}
+
+/// Whether the simulation can be advanced by `dt` seconds: not backwards, not by nothing,
+/// and not by an infinite or NaN time, which would never finish or poison the state.
+pub(crate) fn is_valid_dt(dt: f32) -> bool {
+    dt > 0. && dt.is_finite()
+}
+
+impl SimplePhysics {
+    /// Parameter the simulation outputs to.
+    pub fn param(&self) -> ParamUuid {
+        ParamUuid(self.param)
+    }
+
+    fn gravity(&self, env: &PuppetPhysics) -> f32 {
+        self.gravity * env.gravity * env.pixels_per_meter
+    }
+
+    /// State of the simulation with the bob hanging at rest below `anchor`.
+    pub(crate) fn rest_state(&self, anchor: Vec2) -> PendulumState {
+        PendulumState {
+            bob: anchor + vec2(0., self.length),
+            velocity: Vec2::ZERO,
+            angular_velocity: 0.,
+        }
+    }
+
+    /// Whether the pendulum can be simulated in `env`.
+    ///
+    /// A length, or a frequency for spring pendulums, that isn't positive divides by zero or flips the forces,
+    /// and a non-finite gravity poisons the bob, sending it to infinity or NaN.
+    fn is_simulable(&self, env: &PuppetPhysics) -> bool {
+        let frequency_ok = self.model_type != "SpringPendulum"
+            || (self.frequency > 0. && self.frequency.is_finite());
+        self.length > 0. && self.length.is_finite() && frequency_ok && self.gravity(env).is_finite()
+    }
+
+    /// Advances the simulation by `dt` seconds, with the anchor moved to `anchor`.
+    ///
+    /// Returns the value to give to the output parameter, or `None` if the pendulum can't be simulated
+    /// because of its settings. The state is left as is if `dt` isn't a positive number of seconds.
+    pub(crate) fn step(
+        &self,
+        state: &mut PendulumState,
+        anchor: Vec2,
+        env: &PuppetPhysics,
+        dt: f32,
+    ) -> Option<Vec2> {
+        if !self.is_simulable(env) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(node = ?self.node_state.uuid, "Not simulating degenerate pendulum");
+            return None;
+        }
+        if !is_valid_dt(dt) {
+            return Some(self.output(state, anchor));
+        }
+        let dt = dt.max(MIN_FRAME);
+        let steps = (dt / MAX_STEP).ceil() as usize;
+        let h = dt / steps.max(1) as f32;
+        for _ in 0..steps {
+            match self.model_type.as_str() {
+                "SpringPendulum" => self.tick_spring(state, anchor, env, h),
+                _ => self.tick_rigid(state, anchor, env, h),
+            }
+        }
+        Some(self.output(state, anchor))
+    }
+
+    fn tick_rigid(&self, state: &mut PendulumState, anchor: Vec2, env: &PuppetPhysics, h: f32) {
+        // The angle is measured against the moved anchor, which is what makes the bob swing
+        let offset = state.bob - anchor;
+        let mut angle = f32::atan2(-offset.x, offset.y);
+
+        let length_ratio = self.gravity(env) / self.length;
+        // Gravity may point up, which damps like it would pointing down
+        let critical_damping = 2. * length_ratio.abs().sqrt();
+        let acceleration = -length_ratio * angle.sin()
+            - state.angular_velocity * self.angle_damping * critical_damping;
+
+        state.angular_velocity += acceleration * h;
+        angle += state.angular_velocity * h;
+        state.bob = anchor + vec2(-angle.sin(), angle.cos()) * self.length;
+    }
+
+    fn tick_spring(&self, state: &mut PendulumState, anchor: Vec2, env: &PuppetPhysics, h: f32) {
+        let gravity = self.gravity(env);
+        let stiffness_sqrt = self.frequency * 2. * PI;
+        let stiffness = stiffness_sqrt * stiffness_sqrt;
+        // Shorter at rest so that gravity stretches it back to `length`
+        let rest_length = self.length - gravity / stiffness;
+
+        let offset = state.bob - anchor;
+        let distance = offset.length();
+        let normal = offset.try_normalize().unwrap_or(Vec2::Y);
+        let tangent = normal.perp();
+
+        let angle_damping = 2. * (gravity / self.length).abs().sqrt() * self.angle_damping;
+        let length_damping = 2. * stiffness_sqrt * self.length_damping;
+
+        let force = vec2(0., gravity)
+            - normal * (distance - rest_length) * stiffness
+            - tangent * state.velocity.dot(tangent) * angle_damping
+            - normal * state.velocity.dot(normal) * length_damping;
+
+        state.velocity += force * h;
+        state.bob += state.velocity * h;
+    }
+
+    fn output(&self, state: &PendulumState, anchor: Vec2) -> Vec2 {
+        let offset = (state.bob - anchor) / self.length;
+        let value = match self.map_mode.as_str() {
+            "AngleLength" => vec2(f32::atan2(-offset.x, offset.y) / PI, offset.length()),
+            // Parameters have their Y axis pointing up, and are centered on the rest position
+            _ => vec2(offset.x, 1. - offset.y),
+        };
+        value * self.output_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::nodes::node::NodeUuid;
+    use crate::params::Param;
+    use crate::puppet::Puppet;
+
+    const OUTPUT: ParamUuid = ParamUuid(10);
+
+    fn pendulum(model_type: &str) -> SimplePhysics {
+        SimplePhysics {
+            node_state: NodeState::new(NodeUuid(2), "Physics"),
+            param: OUTPUT.0,
+            model_type: model_type.to_owned(),
+            map_mode: "XY".to_owned(),
+            gravity: 1.,
+            length: 100.,
+            frequency: 1.,
+            angle_damping: 0.5,
+            length_damping: 0.5,
+            output_scale: Vec2::ONE,
+        }
+    }
+
+    fn puppet(physics: SimplePhysics) -> Puppet {
+        let mut builder = ModelBuilder::new(NodeState::new(NodeUuid(1), "Root"));
+        builder.add_node(NodeUuid(1), physics).unwrap();
+        let output = Param::new(OUTPUT, "Output", true, vec2(-1., -1.), vec2(1., 1.));
+        builder.add_param(output).unwrap();
+        builder.build().puppet
+    }
+
+    /// Moves the root so that the pendulum swings.
+    fn push(puppet: &mut Puppet) {
+        let root = puppet.nodes.get_node_mut(NodeUuid(1)).unwrap();
+        root.get_node_state_mut().transform.translation.x += 20.;
+    }
+
+    #[test]
+    fn zero_dt_leaves_state_unchanged() {
+        let physics = pendulum("RigidPendulum");
+        let env = PuppetPhysics::default();
+        let mut state = physics.rest_state(Vec2::ZERO);
+        physics.step(&mut state, vec2(20., 0.), &env, 1. / 60.);
+        let before = state;
+
+        for dt in [0., -1., f32::NAN, f32::INFINITY] {
+            let output = physics.step(&mut state, vec2(20., 0.), &env, dt).unwrap();
+            assert_eq!(state, before);
+            assert!(output.is_finite());
+        }
+    }
+
+    #[test]
+    fn zero_dt_update_keeps_params() {
+        let mut puppet = puppet(pendulum("RigidPendulum"));
+        push(&mut puppet);
+        puppet.update_physics(1. / 60.);
+        let before = puppet.snapshot();
+
+        puppet.update_physics(0.);
+        assert_eq!(puppet.snapshot(), before);
+        assert!(before.values.values().all(|value| value.is_finite()));
+    }
+
+    #[test]
+    fn degenerate_pendulums_stay_finite() {
+        let zero_length = SimplePhysics {
+            length: 0.,
+            ..pendulum("RigidPendulum")
+        };
+        let zero_frequency = SimplePhysics {
+            frequency: 0.,
+            ..pendulum("SpringPendulum")
+        };
+        let negative_gravity = [
+            SimplePhysics {
+                gravity: -1.,
+                ..pendulum("RigidPendulum")
+            },
+            SimplePhysics {
+                gravity: -1.,
+                ..pendulum("SpringPendulum")
+            },
+        ];
+
+        for physics in [zero_length, zero_frequency]
+            .into_iter()
+            .chain(negative_gravity)
+        {
+            let mut puppet = puppet(physics);
+            for _ in 0..10 {
+                push(&mut puppet);
+                puppet.update_physics(1. / 60.);
+            }
+            let value = puppet.get_param_by_uuid(OUTPUT).unwrap();
+            assert!(value.is_finite(), "{value}");
+        }
+    }
+}
@@ -1,31 +1,82 @@
 pub mod drivers;
 pub mod node;
 pub mod node_tree;
+pub mod tree_diff;
 
 pub mod composite;
 pub mod drawable;
 pub mod part;
 
+/// Implements [`Node`](node::Node) for a struct holding its [`NodeState`](node::NodeState) in `$field`.
+///
+/// Types without a `$kind` get [`NodeKind::Unknown`](node::NodeKind::Unknown).
+/// Methods with a default implementation, like [`Node::deform_children`](node::Node::deform_children),
+/// can be overridden in a trailing block.
 #[macro_export]
 macro_rules! impl_node {
     ($t:ty, $field:ident) => {
-        #[typetag::serde]
-        impl $crate::nodes::node::Node for $t {
-            fn get_node_state(&self) -> &$crate::nodes::node::NodeState {
-                &self.$field
-            }
+        $crate::impl_node!($t, $field, Unknown);
+    };
+    ($t:ty, $field:ident, $kind:ident) => {
+        $crate::impl_node!($t, $field, $kind, {});
+    };
+    ($t:ty, $field:ident, $kind:ident, { $($items:tt)* }) => {
+        $crate::__impl_node_serde! {
+            impl $crate::nodes::node::Node for $t {
+                fn get_node_state(&self) -> &$crate::nodes::node::NodeState {
+                    &self.$field
+                }
 
-            fn get_node_state_mut(&mut self) -> &mut $crate::nodes::node::NodeState {
-                &mut self.$field
-            }
+                fn get_node_state_mut(&mut self) -> &mut $crate::nodes::node::NodeState {
+                    &mut self.$field
+                }
 
-            fn as_any(&self) -> &dyn ::core::any::Any {
-                self
-            }
+                fn as_any(&self) -> &dyn ::core::any::Any {
+                    self
+                }
+
+                fn as_any_mut(&mut self) -> &mut dyn ::core::any::Any {
+                    self
+                }
+
+                fn node_type_name(&self) -> &'static str {
+                    stringify!($t)
+                }
+
+                fn kind(&self) -> $crate::nodes::node::NodeKind {
+                    $crate::nodes::node::NodeKind::$kind
+                }
+
+                fn dyn_eq(&self, other: &dyn $crate::nodes::node::Node) -> bool {
+                    other.as_any().downcast_ref::<Self>() == Some(self)
+                }
 
-            fn as_any_mut(&mut self) -> &mut dyn ::core::any::Any {
-                self
+                fn dyn_clone(&self) -> Box<dyn $crate::nodes::node::Node> {
+                    Box::new(self.clone())
+                }
+
+                $($items)*
             }
         }
     };
 }
+
+// The `serde` feature is checked here, in inox2d, rather than in the crate calling `impl_node!`.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_node_serde {
+    ($impl:item) => {
+        #[typetag::serde]
+        $impl
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_node_serde {
+    ($impl:item) => {
+        $impl
+    };
+}
@@ -1,38 +1,163 @@
 use std::any::Any;
 use std::fmt::Debug;
 
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+use glam::Vec2;
+
 use crate::math::transform::Transform;
 
-#[derive(
-    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
-)]
+use super::drawable::Drawable;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(transparent)]
 pub struct NodeUuid(pub(crate) u32);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl NodeUuid {
+    /// Makes a UUID from its raw value, as stored in puppet files and shown by Inochi Creator.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// Raw value of the UUID.
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeState {
     pub uuid: NodeUuid,
     pub name: String,
     pub enabled: bool,
     pub zsort: f32,
+    /// Zsort pinning the node to a fixed layer.
+    ///
+    /// When set, it replaces the zsort accumulated from the node's ancestors
+    /// (and the node's own `zsort`) instead of adding to it.
+    /// Descendants still accumulate their zsort on top of it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub zsort_override: Option<f32>,
     pub transform: Transform,
-    #[serde(rename = "lockToRoot")]
+    #[cfg_attr(feature = "serde", serde(rename = "lockToRoot"))]
     pub lock_to_root: bool,
+    /// Notes left on the node for collaborators, e.g. by custom tools.
+    ///
+    /// Also read from a `comment` field.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, alias = "comment", skip_serializing_if = "Option::is_none")
+    )]
+    pub notes: Option<String>,
+    /// Offset applied to `transform` by parameter bindings.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub offset_transform: Transform,
+    /// Offset applied to `zsort` by parameter bindings.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub offset_zsort: f32,
+}
+
+impl NodeState {
+    /// Makes an enabled node at the origin of its parent.
+    pub fn new(uuid: NodeUuid, name: impl Into<String>) -> Self {
+        Self {
+            uuid,
+            name: name.into(),
+            enabled: true,
+            zsort: 0.,
+            zsort_override: None,
+            transform: Transform::default(),
+            lock_to_root: false,
+            notes: None,
+            offset_transform: Transform::default(),
+            offset_zsort: 0.,
+        }
+    }
+
+    /// Transform of the node relative to its parent, with binding offsets applied.
+    pub fn local_transform(&self) -> Transform {
+        let mut transform = Transform::new()
+            .with_translation(self.transform.translation + self.offset_transform.translation)
+            .with_rotation(self.transform.rotation + self.offset_transform.rotation)
+            .with_scale(self.transform.scale * self.offset_transform.scale);
+        transform.pixel_snap = self.transform.pixel_snap;
+        transform.update();
+        transform
+    }
+
+    /// Zsort of the node relative to its parent, with binding offsets applied.
+    pub fn local_zsort(&self) -> f32 {
+        self.zsort + self.offset_zsort
+    }
+
+    /// Clears the offsets set by parameter bindings.
+    pub fn reset_offsets(&mut self) {
+        self.offset_transform = Transform::default();
+        self.offset_zsort = 0.;
+    }
+}
+
+/// Type of a node, to match on without downcasting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NodeKind {
+    /// A plain [`NodeState`], only used to group and transform its children.
+    Node,
+    Part,
+    Composite,
+    SimplePhysics,
+    Mask,
+    MeshGroup,
+    PathDeform,
+    /// A node type defined outside of this crate.
+    Unknown,
 }
 
 // TODO: make a derive macro for this
-#[typetag::serde(tag = "type")]
+#[cfg_attr(feature = "serde", typetag::serde(tag = "type"))]
 pub trait Node: Debug + Any {
     fn get_node_state(&self) -> &NodeState;
     fn get_node_state_mut(&mut self) -> &mut NodeState;
 
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Name of the node's type, as stored in puppet files.
+    fn node_type_name(&self) -> &'static str;
+
+    /// Type of the node, [`NodeKind::Unknown`] unless overridden.
+    fn kind(&self) -> NodeKind {
+        NodeKind::Unknown
+    }
+
+    /// The node as a [`Drawable`], for parts and composites.
+    fn as_drawable(&self) -> Option<&dyn Drawable> {
+        None
+    }
+
+    /// Deforms the vertices of the node's children, for nodes like mesh groups that bend what's in them.
+    ///
+    /// `children_verts` holds the vertices of some of the node's part children, in their order in the tree.
+    /// They are in the space of each part's mesh, with the part's own deform already applied,
+    /// see [`NodeTree::deform_children`](super::node_tree::NodeTree::deform_children).
+    /// Does nothing by default.
+    fn deform_children(&self, children_verts: &mut [Vec<Vec2>]) {
+        let _ = children_verts;
+    }
+
+    /// Whether both nodes are of the same type and equal.
+    fn dyn_eq(&self, other: &dyn Node) -> bool;
+
+    fn dyn_clone(&self) -> Box<dyn Node>;
 }
 
-#[typetag::serde(name = "Node")]
+#[cfg_attr(feature = "serde", typetag::serde(name = "Node"))]
 impl Node for NodeState {
     fn get_node_state(&self) -> &NodeState {
         self
@@ -49,4 +174,20 @@ impl Node for NodeState {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
+
+    fn node_type_name(&self) -> &'static str {
+        "Node"
+    }
+
+    fn kind(&self) -> NodeKind {
+        NodeKind::Node
+    }
+
+    fn dyn_eq(&self, other: &dyn Node) -> bool {
+        other.as_any().downcast_ref::<Self>() == Some(self)
+    }
+
+    fn dyn_clone(&self) -> Box<dyn Node> {
+        Box::new(self.clone())
+    }
 }
@@ -1,14 +1,23 @@
-use std::any::TypeId;
-use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 
-use indextree::{Arena, NodeId};
+use glam::{Mat4, Vec2, Vec3};
+use indextree::{Arena, NodeEdge, NodeId};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+use crate::math::rect::Rect;
+use crate::math::transform::{snap_to_pixels, Transform};
+
 use super::composite::Composite;
-use super::node::{Node, NodeUuid};
+use super::drawable::{BlendMode, DrawState, Mask, ResolvedMask};
+use super::node::{Node, NodeKind, NodeUuid};
+use super::part::Part;
 
 /// Node tree struct who's only purpose is to be deserialized into an arena.
+#[cfg(feature = "serde")]
 #[derive(Debug, Serialize, Deserialize)]
 struct SNodeTree {
     #[serde(flatten)]
@@ -17,6 +26,13 @@ struct SNodeTree {
     children: Vec<SNodeTree>,
 }
 
+#[cfg(feature = "serde")]
+thread_local! {
+    /// Arena the next tree deserialized on this thread is built in, see [`NodeTree::deserialize_in`].
+    static SPARE_ARENA: RefCell<Option<Arena<Box<dyn Node>>>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "serde")]
 impl SNodeTree {
     /// Moves the entire tree into an arena.
     /// Returns the Node ID of the root of the tree.
@@ -50,14 +66,114 @@ impl SNodeTree {
     }
 }
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum NodeTreeError {
+    #[error("No node with UUID {0:?}")]
+    NotFound(NodeUuid),
+    #[error("Node names can't be empty")]
+    EmptyName,
+    #[error("Node {0:?} already has this name")]
+    DuplicateName(NodeUuid),
+    #[error("Node {0:?} is not a drawable")]
+    NotDrawable(NodeUuid),
+    #[error("There already is a node with UUID {0:?}")]
+    DuplicateUuid(NodeUuid),
+}
+
+/// Order in which [`NodeTree::zsorted_by`] returns nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ZSortDirection {
+    /// Lowest zsort first.
+    Ascending,
+    /// Highest zsort first, which is painter's order for Inochi2D.
+    #[default]
+    Descending,
+}
+
+/// Callbacks for a depth-first walk of a tree, see [`NodeTree::visit`].
+///
+/// `depth` is 0 for the root.
+pub trait NodeVisitor {
+    /// Called on a node before its children.
+    fn enter(&mut self, _uuid: NodeUuid, _node: &dyn Node, _depth: usize) {}
+
+    /// Called on a node after its children.
+    fn leave(&mut self, _uuid: NodeUuid, _node: &dyn Node, _depth: usize) {}
+}
+
 #[derive(Debug)]
 pub struct NodeTree {
     pub root: indextree::NodeId,
     pub arena: Arena<Box<dyn Node>>,
     pub uuids: BTreeMap<NodeUuid, indextree::NodeId>,
+    /// Scale of the whole puppet, see [`Puppet::set_scale`](crate::puppet::Puppet::set_scale).
+    pub(crate) scale: f32,
 }
 
 impl NodeTree {
+    /// Makes a tree with only a root.
+    pub fn new(root: Box<dyn Node>) -> Self {
+        let uuid = root.get_node_state().uuid;
+        let mut arena = Arena::new();
+        let root = arena.new_node(root);
+        Self {
+            root,
+            arena,
+            uuids: BTreeMap::from([(uuid, root)]),
+            scale: 1.,
+        }
+    }
+
+    /// Adds a node as the last child of `parent`.
+    pub fn add_node(&mut self, parent: NodeUuid, node: Box<dyn Node>) -> Result<(), NodeTreeError> {
+        let uuid = node.get_node_state().uuid;
+        if self.uuids.contains_key(&uuid) {
+            return Err(NodeTreeError::DuplicateUuid(uuid));
+        }
+        let &parent_id = self
+            .uuids
+            .get(&parent)
+            .ok_or(NodeTreeError::NotFound(parent))?;
+
+        let node_id = self.arena.new_node(node);
+        parent_id.append(node_id, &mut self.arena);
+        self.uuids.insert(uuid, node_id);
+        Ok(())
+    }
+
+    /// Rebuilds [`NodeTree::uuids`] from the nodes reachable from the root,
+    /// e.g. after adding or removing nodes through [`NodeTree::arena`] directly.
+    ///
+    /// If several nodes share a UUID, the first one in tree order is kept.
+    pub fn rebuild_uuid_index(&mut self) {
+        self.uuids.clear();
+        for node_id in self.root.descendants(&self.arena) {
+            let Some(node) = self.arena.get(node_id) else {
+                continue;
+            };
+            self.uuids
+                .entry(node.get().get_node_state().uuid)
+                .or_insert(node_id);
+        }
+    }
+
+    /// Runs `deserialize`, building the first tree it deserializes in `arena` to reuse its allocation.
+    ///
+    /// `arena` is cleared, and is left empty if a tree took it.
+    #[cfg(feature = "parse")]
+    pub(crate) fn deserialize_in<T>(
+        arena: &mut Arena<Box<dyn Node>>,
+        deserialize: impl FnOnce() -> T,
+    ) -> T {
+        arena.clear();
+        SPARE_ARENA.set(Some(std::mem::take(arena)));
+        let result = deserialize();
+        if let Some(spare) = SPARE_ARENA.take() {
+            *arena = spare;
+        }
+        result
+    }
+
     fn get_internal_node(&self, uuid: NodeUuid) -> Option<&indextree::Node<Box<dyn Node>>> {
         self.arena.get(*self.uuids.get(&uuid)?)
     }
@@ -95,17 +211,539 @@ impl NodeTree {
         )
     }
 
-    pub fn ancestors(&self, uuid: NodeUuid) -> indextree::Ancestors<Box<dyn Node>> {
+    /// Removes a node from the tree, its children taking its place in its parent.
+    ///
+    /// The root can't be removed. Returns whether the node was removed.
+    pub fn remove_node(&mut self, uuid: NodeUuid) -> bool {
+        match self.uuids.get(&uuid) {
+            Some(&node_id) if node_id != self.root => {
+                node_id.remove(&mut self.arena);
+                self.uuids.remove(&uuid);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes a node along with all its descendants, returning their UUIDs.
+    ///
+    /// The root can't be removed, nothing is removed if `uuid` is the root or isn't in the tree.
+    pub fn remove_subtree(&mut self, uuid: NodeUuid) -> Vec<NodeUuid> {
+        let Some(&node_id) = self.uuids.get(&uuid) else {
+            return Vec::new();
+        };
+        if node_id == self.root {
+            return Vec::new();
+        }
+
+        let removed: Vec<_> = node_id
+            .descendants(&self.arena)
+            .filter_map(|id| Some(self.arena.get(id)?.get().get_node_state().uuid))
+            .collect();
+        node_id.remove_subtree(&mut self.arena);
+        for uuid in &removed {
+            self.uuids.remove(uuid);
+        }
+        removed
+    }
+
+    /// Renames a node, returning its old name.
+    ///
+    /// Leading and trailing whitespace is trimmed, like Inochi Creator does.
+    /// Several nodes can share a name, see [`NodeTree::rename_unique`] to prevent that.
+    pub fn rename(&mut self, uuid: NodeUuid, name: &str) -> Result<String, NodeTreeError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(NodeTreeError::EmptyName);
+        }
+        let node = self
+            .get_node_mut(uuid)
+            .ok_or(NodeTreeError::NotFound(uuid))?;
+        Ok(std::mem::replace(
+            &mut node.get_node_state_mut().name,
+            name.to_owned(),
+        ))
+    }
+
+    /// Renames a node like [`NodeTree::rename`], unless another node already has that name.
+    pub fn rename_unique(&mut self, uuid: NodeUuid, name: &str) -> Result<String, NodeTreeError> {
+        let trimmed = name.trim();
+        if trimmed.is_empty() {
+            return Err(NodeTreeError::EmptyName);
+        }
+        if let Some((other, _)) = self
+            .iter()
+            .find(|&(other, node)| other != uuid && node.get_node_state().name == trimmed)
+        {
+            return Err(NodeTreeError::DuplicateName(other));
+        }
+        self.rename(uuid, name)
+    }
+
+    /// Notes left on a node, see [`NodeState::notes`](super::node::NodeState::notes).
+    ///
+    /// Returns `None` if the node has no notes or doesn't exist.
+    pub fn notes(&self, uuid: NodeUuid) -> Option<&str> {
+        self.get_node(uuid)?.get_node_state().notes.as_deref()
+    }
+
+    /// Replaces the notes left on a node, or removes them with `None`, returning the previous ones.
+    pub fn set_notes(
+        &mut self,
+        uuid: NodeUuid,
+        notes: Option<String>,
+    ) -> Result<Option<String>, NodeTreeError> {
+        let node = self
+            .get_node_mut(uuid)
+            .ok_or(NodeTreeError::NotFound(uuid))?;
+        Ok(std::mem::replace(
+            &mut node.get_node_state_mut().notes,
+            notes,
+        ))
+    }
+
+    /// Pins a node to a fixed zsort, or unpins it with `None`.
+    ///
+    /// See [`NodeState::zsort_override`](super::node::NodeState::zsort_override).
+    /// Returns whether the node exists.
+    pub fn set_zsort_override(&mut self, uuid: NodeUuid, zsort: Option<f32>) -> bool {
+        let Some(node) = self.get_node_mut(uuid) else {
+            return false;
+        };
+        node.get_node_state_mut().zsort_override = zsort;
+        true
+    }
+
+    /// Draw state of a part or composite.
+    pub(crate) fn drawable(&self, uuid: NodeUuid) -> Result<&DrawState, NodeTreeError> {
+        let node = self.get_node(uuid).ok_or(NodeTreeError::NotFound(uuid))?;
+        let drawable = node.as_drawable().ok_or(NodeTreeError::NotDrawable(uuid))?;
+        Ok(drawable.draw_state())
+    }
+
+    pub(crate) fn drawable_mut(&mut self, uuid: NodeUuid) -> Result<&mut DrawState, NodeTreeError> {
+        let node = self
+            .get_node_mut(uuid)
+            .ok_or(NodeTreeError::NotFound(uuid))?;
+        let node = node.as_any_mut();
+        // Checked with `is` first, as borrowck rejects returning from chained `if let`s
+        if node.is::<Part>() {
+            Ok(&mut node.downcast_mut::<Part>().unwrap().draw_state)
+        } else if node.is::<Composite>() {
+            Ok(&mut node.downcast_mut::<Composite>().unwrap().draw_state)
+        } else {
+            Err(NodeTreeError::NotDrawable(uuid))
+        }
+    }
+
+    /// Blend mode of a drawable node.
+    pub fn blend_mode(&self, uuid: NodeUuid) -> Result<BlendMode, NodeTreeError> {
+        Ok(self.drawable(uuid)?.blend_mode)
+    }
+
+    /// Changes the blend mode of a drawable node, returning the previous one so it can be restored.
+    pub fn set_blend_mode(
+        &mut self,
+        uuid: NodeUuid,
+        mode: BlendMode,
+    ) -> Result<BlendMode, NodeTreeError> {
+        Ok(std::mem::replace(
+            &mut self.drawable_mut(uuid)?.blend_mode,
+            mode,
+        ))
+    }
+
+    /// Masks of a drawable node, with their thresholds resolved and their sources looked up.
+    ///
+    /// Returns nothing if the node doesn't exist or isn't drawable.
+    pub fn masks_of(&self, uuid: NodeUuid) -> Vec<ResolvedMask> {
+        let Ok(drawable) = self.drawable(uuid) else {
+            return Vec::new();
+        };
+        drawable
+            .masks
+            .iter()
+            .map(|mask| ResolvedMask {
+                source: mask.source,
+                mode: mask.mode,
+                threshold: drawable.threshold_for(mask),
+                source_node_exists: self.uuids.contains_key(&mask.source),
+            })
+            .collect()
+    }
+
+    /// Names of the ancestors of a node and of the node itself, from the root's child down to the node,
+    /// e.g. `["Body", "Head", "Eyes", "EyeL"]`. The root isn't included, so its path is empty.
+    ///
+    /// Returns `None` if there is no node with that UUID.
+    pub fn path(&self, uuid: NodeUuid) -> Option<Vec<String>> {
+        let node_id = self.uuids.get(&uuid)?;
+        let mut path: Vec<_> = node_id
+            .ancestors(&self.arena)
+            .take_while(|&ancestor| ancestor != self.root)
+            .filter_map(|ancestor| self.arena.get(ancestor))
+            .map(|ancestor| ancestor.get().get_node_state().name.clone())
+            .collect();
+        path.reverse();
+        Some(path)
+    }
+
+    /// Finds a node from a path given by [`NodeTree::path`], with the names separated by slashes,
+    /// e.g. `"Body/Head/Eyes/EyeL"`. An empty path is the root.
+    ///
+    /// When several siblings have the same name, the first one is taken,
+    /// even if the rest of the path is found under another one.
+    pub fn find_by_path(&self, path: &str) -> Option<NodeUuid> {
+        let mut node_id = self.root;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node_id = node_id.children(&self.arena).find(|&child| {
+                self.arena
+                    .get(child)
+                    .is_some_and(|child| child.get().get_node_state().name == name)
+            })?;
+        }
+        Some(self.arena.get(node_id)?.get().get_node_state().uuid)
+    }
+
+    /// UUIDs of every node of the tree, sorted, so in the same order whatever the structure of the tree.
+    pub fn all_uuids(&self) -> impl Iterator<Item = NodeUuid> + '_ {
+        self.uuids.keys().copied()
+    }
+
+    /// Iterates over every node of the tree, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeUuid, &dyn Node)> + '_ {
+        self.arena
+            .iter()
+            .filter(|node| !node.is_removed())
+            .map(|node| {
+                let node = node.get().as_ref();
+                (node.get_node_state().uuid, node)
+            })
+    }
+
+    /// Iterates mutably over every node of the tree, in no particular order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (NodeUuid, &mut dyn Node)> + '_ {
+        self.arena
+            .iter_mut()
+            .filter(|node| !node.is_removed())
+            .map(|node| {
+                let node = node.get_mut().as_mut();
+                (node.get_node_state().uuid, node)
+            })
+    }
+
+    /// Walks the tree depth-first from the root, children in order,
+    /// calling [`NodeVisitor::enter`] and [`NodeVisitor::leave`] around the children of every node.
+    pub fn visit(&self, visitor: &mut impl NodeVisitor) {
+        let mut depth = 0;
+        for edge in self.root.traverse(&self.arena) {
+            match edge {
+                NodeEdge::Start(id) => {
+                    let node = self.arena[id].get().as_ref();
+                    visitor.enter(node.get_node_state().uuid, node, depth);
+                    depth += 1;
+                }
+                NodeEdge::End(id) => {
+                    depth -= 1;
+                    let node = self.arena[id].get().as_ref();
+                    visitor.leave(node.get_node_state().uuid, node, depth);
+                }
+            }
+        }
+    }
+
+    pub fn ancestors(&self, uuid: NodeUuid) -> indextree::Ancestors<'_, Box<dyn Node>> {
         self.uuids[&uuid].ancestors(&self.arena)
     }
 
+    /// Copies a node and all of its descendants into a new tree, the node becoming its root.
+    pub fn subtree(&self, uuid: NodeUuid) -> Option<NodeTree> {
+        fn copy_children(
+            tree: &NodeTree,
+            from: NodeId,
+            to: NodeId,
+            arena: &mut Arena<Box<dyn Node>>,
+        ) {
+            for child in from.children(&tree.arena) {
+                let Some(node) = tree.arena.get(child) else {
+                    continue;
+                };
+                let node_id = arena.new_node(node.get().dyn_clone());
+                to.append(node_id, arena);
+                copy_children(tree, child, node_id, arena);
+            }
+        }
+
+        let &node_id = self.uuids.get(&uuid)?;
+        let mut arena = Arena::new();
+        let root = arena.new_node(self.arena.get(node_id)?.get().dyn_clone());
+        copy_children(self, node_id, root, &mut arena);
+        let mut tree = NodeTree {
+            root,
+            arena,
+            uuids: BTreeMap::new(),
+            scale: 1.,
+        };
+        tree.rebuild_uuid_index();
+        Some(tree)
+    }
+
+    /// Transform of a node in puppet space, with binding offsets applied.
+    ///
+    /// Nodes that are locked to the root ignore the transforms of their ancestors,
+    /// except for the root's.
+    pub fn world_transform(&self, uuid: NodeUuid) -> Option<Transform> {
+        self.world_matrix(uuid).map(Transform::from_matrix)
+    }
+
+    /// Ancestors of a node whose transforms apply to it, with their [local transforms](super::node::NodeState::local_transform),
+    /// node-first: from its parent up to the root.
+    ///
+    /// The node itself isn't included. Like for [`NodeTree::world_transform`], ancestors locked to the root
+    /// skip straight to it, so the product of the matrices root-first, times the node's local transform,
+    /// gives its transform in puppet space, without the puppet's scale nor pixel snapping.
+    /// Yields nothing if there is no node with that UUID.
+    pub fn ancestor_transforms(
+        &self,
+        uuid: NodeUuid,
+    ) -> impl Iterator<Item = (NodeUuid, Transform)> + '_ {
+        let mut chain = Vec::new();
+        let mut locked = false;
+        for ancestor in self
+            .uuids
+            .get(&uuid)
+            .into_iter()
+            .flat_map(|id| id.ancestors(&self.arena))
+        {
+            let state = self.arena[ancestor].get().get_node_state();
+            if locked && ancestor != self.root {
+                continue;
+            }
+            if state.uuid != uuid {
+                chain.push((state.uuid, state.local_transform()));
+            }
+            locked |= state.lock_to_root;
+        }
+        chain.into_iter()
+    }
+
+    pub(crate) fn world_matrix(&self, uuid: NodeUuid) -> Option<Mat4> {
+        let pixel_snap = self.get_node(uuid)?.get_node_state().transform.pixel_snap;
+        let matrix = self.scale_matrix() * self.rig_matrix(uuid)?;
+        if pixel_snap {
+            Some(snap_to_pixels(matrix))
+        } else {
+            Some(matrix)
+        }
+    }
+
+    /// Scale of the whole puppet, applied on top of the transforms of all the nodes.
+    fn scale_matrix(&self) -> Mat4 {
+        Mat4::from_scale(Vec3::new(self.scale, self.scale, 1.))
+    }
+
+    /// Matrix of a node in puppet space as rigged, without the puppet's scale nor pixel snapping.
+    pub(crate) fn rig_matrix(&self, uuid: NodeUuid) -> Option<Mat4> {
+        let mut matrix = Mat4::IDENTITY;
+        for ancestor in self.uuids.get(&uuid)?.ancestors(&self.arena) {
+            let state = self.arena.get(ancestor)?.get().get_node_state();
+            matrix = state.local_transform().matrix() * matrix;
+
+            if state.lock_to_root && ancestor != self.root {
+                let root = self.arena.get(self.root)?.get().get_node_state();
+                matrix = root.local_transform().matrix() * matrix;
+                break;
+            }
+        }
+        Some(matrix)
+    }
+
+    /// Matrix of the node's parent in puppet space, or of the root for a node locked to it.
+    pub(crate) fn parent_matrix(&self, uuid: NodeUuid) -> Option<Mat4> {
+        let node_id = *self.uuids.get(&uuid)?;
+        if node_id == self.root {
+            return Some(self.scale_matrix());
+        }
+        let state = self.arena.get(node_id)?.get().get_node_state();
+        if state.lock_to_root {
+            let root = self.arena.get(self.root)?.get().get_node_state();
+            return Some(self.scale_matrix() * root.local_transform().matrix());
+        }
+        let parent = self.arena.get(node_id.parent(&self.arena)?)?;
+        self.world_matrix(parent.get().get_node_state().uuid)
+    }
+
+    /// Final position of the vertices of a part in puppet space, see [`Part::baked_verts`].
+    ///
+    /// Returns `None` if there is no part with that UUID.
+    pub fn baked_verts(&self, uuid: NodeUuid) -> Option<Vec<Vec2>> {
+        let part = self.get_node(uuid)?.as_any().downcast_ref::<Part>()?;
+        let parent = self.parent_matrix(uuid)?;
+        let mut verts = Vec::new();
+        part.bake_verts_into(parent, &mut verts);
+        Some(verts)
+    }
+
+    /// Lets the parents of the given parts deform them, see [`Node::deform_children`].
+    ///
+    /// The parts of a same parent are deformed in one go, the new positions being stored in their deform offsets.
+    /// UUIDs that aren't parts are ignored.
+    pub fn deform_children(&mut self, parts: &BTreeSet<NodeUuid>) {
+        let mut siblings: BTreeMap<NodeId, Vec<NodeId>> = BTreeMap::new();
+        for uuid in parts {
+            let Some(&node_id) = self.uuids.get(uuid) else {
+                continue;
+            };
+            let is_part = self.arena[node_id].get().as_any().is::<Part>();
+            if let Some(parent) = node_id.parent(&self.arena).filter(|_| is_part) {
+                siblings.entry(parent).or_default().push(node_id);
+            }
+        }
+
+        for (parent, deformed) in siblings {
+            // In tree order, as the parents expect
+            let children: Vec<_> = parent
+                .children(&self.arena)
+                .filter(|child| deformed.contains(child))
+                .collect();
+            let mut verts: Vec<Vec<Vec2>> = children
+                .iter()
+                .filter_map(|&child| self.arena[child].get().as_any().downcast_ref::<Part>())
+                .map(|part| {
+                    let offsets = part.deform.iter().chain(std::iter::repeat(&Vec2::ZERO));
+                    part.mesh
+                        .vertices
+                        .iter()
+                        .zip(offsets)
+                        .map(|(&v, &d)| v + d)
+                        .collect()
+                })
+                .collect();
+            self.arena[parent].get().deform_children(&mut verts);
+
+            for (child, verts) in children.into_iter().zip(verts) {
+                let Some(part) = self.arena[child]
+                    .get_mut()
+                    .as_any_mut()
+                    .downcast_mut::<Part>()
+                else {
+                    continue;
+                };
+                part.deform.resize(part.mesh.vertices.len(), Vec2::ZERO);
+                let rest = part.mesh.vertices.iter();
+                for ((offset, &rest), vert) in part.deform.iter_mut().zip(rest).zip(verts) {
+                    // Only touching what moved, so that parents that don't deform keep the offsets exact
+                    if rest + *offset != vert {
+                        *offset = vert - rest;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Opacity a node is drawn with: its own opacity multiplied by the opacity of the composites it's in,
+    /// as composites are drawn with their opacity once their children are drawn in them.
+    ///
+    /// Nodes that aren't drawables have no opacity of their own.
+    /// Returns `None` if there is no node with that UUID.
+    pub fn world_opacity(&self, uuid: NodeUuid) -> Option<f32> {
+        let node_id = self.uuids.get(&uuid)?;
+        let own = self.drawable(uuid).map_or(1., DrawState::local_opacity);
+        let composites: f32 = node_id
+            .ancestors(&self.arena)
+            .skip(1)
+            .filter_map(|ancestor| self.arena.get(ancestor))
+            .filter_map(|ancestor| ancestor.get().as_any().downcast_ref::<Composite>())
+            .map(|composite| composite.draw_state.local_opacity())
+            .product();
+        Some(own * composites)
+    }
+
+    /// Whether a node and all its ancestors are enabled, which is needed for the node to be drawn.
+    pub fn is_render_enabled(&self, uuid: NodeUuid) -> bool {
+        let Some(node_id) = self.uuids.get(&uuid) else {
+            return false;
+        };
+        node_id.ancestors(&self.arena).all(|ancestor| {
+            self.arena
+                .get(ancestor)
+                .is_some_and(|ancestor| ancestor.get().get_node_state().enabled)
+        })
+    }
+
+    /// Area covered by the parts among a node and its descendants, in puppet space.
+    ///
+    /// Returns `None` if there are no such parts.
+    pub fn bounds(&self, uuid: NodeUuid) -> Option<Rect> {
+        let mut verts = Vec::new();
+        self.uuids
+            .get(&uuid)?
+            .descendants(&self.arena)
+            .filter_map(|node_id| self.arena.get(node_id))
+            .filter_map(|node| node.get().as_any().downcast_ref::<Part>())
+            .filter_map(|part| {
+                let parent = self.parent_matrix(part.node_state.uuid)?;
+                verts.clear();
+                part.bake_verts_into(parent, &mut verts);
+                Rect::from_points(verts.iter().copied())
+            })
+            .reduce(Rect::union)
+    }
+
+    /// Nearest composite that contains the node, if any.
+    ///
+    /// Nodes inside a composite are rendered into that composite's offscreen target,
+    /// the others render directly to the main target.
+    pub fn composite_ancestor(&self, uuid: NodeUuid) -> Option<NodeUuid> {
+        self.uuids
+            .get(&uuid)?
+            .ancestors(&self.arena)
+            .skip(1)
+            .filter_map(|ancestor| self.arena.get(ancestor))
+            .map(|ancestor| ancestor.get())
+            .find(|ancestor| ancestor.as_any().is::<Composite>())
+            .map(|composite| composite.get_node_state().uuid)
+    }
+
+    /// Masks a composite inherits from the composites it is nested in, nearest first.
+    ///
+    /// See [`Composite::propagates_masks`] and [`Composite::inherits_masks`].
+    /// Returns nothing if the node isn't a composite.
+    pub fn inherited_masks(&self, uuid: NodeUuid) -> Vec<&Mask> {
+        let mut masks = Vec::new();
+        let Some(mut composite) = self
+            .get_node(uuid)
+            .and_then(|n| n.as_any().downcast_ref::<Composite>())
+        else {
+            return masks;
+        };
+
+        while composite.inherits_masks() {
+            let Some(parent) = self
+                .composite_ancestor(composite.node_state.uuid)
+                .and_then(|parent| self.get_node(parent))
+                .and_then(|parent| parent.as_any().downcast_ref::<Composite>())
+            else {
+                break;
+            };
+            if parent.propagates_masks() {
+                masks.extend(&parent.draw_state.masks);
+            }
+            composite = parent;
+        }
+
+        masks
+    }
+
     fn rec_zsorts_from_root(&self, node: &dyn Node, zsort: f32) -> Vec<(NodeUuid, f32)> {
         let node_state = node.get_node_state();
-        let zsort = zsort + node_state.zsort;
+        let zsort = node_state
+            .zsort_override
+            .unwrap_or(zsort + node_state.local_zsort());
         let mut vec = vec![(node_state.uuid, zsort)];
 
         // Skip composite children because they're a special case
-        if node.type_id() != TypeId::of::<Composite>() {
+        if node.kind() != NodeKind::Composite {
             for child_uuid in self.get_children_uuids(node_state.uuid).unwrap_or_default() {
                 if let Some(child) = self.get_node(child_uuid) {
                     vec.extend(self.rec_zsorts_from_root(child.as_ref(), zsort));
@@ -116,19 +754,92 @@ impl NodeTree {
         vec
     }
 
-    fn sort_uuids_by_zsort(&self, mut uuid_zsorts: Vec<(NodeUuid, f32)>) -> Vec<NodeUuid> {
-        uuid_zsorts.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().reverse());
+    fn sort_uuids_by_zsort(
+        &self,
+        mut uuid_zsorts: Vec<(NodeUuid, f32)>,
+        direction: ZSortDirection,
+    ) -> Vec<NodeUuid> {
+        uuid_zsorts.sort_by(|a, b| {
+            // Total order so that NaN zsorts sort above infinity instead of panicking,
+            // adding 0 so that -0 and 0 still tie
+            let ordering = (a.1 + 0.).total_cmp(&(b.1 + 0.));
+            match direction {
+                ZSortDirection::Ascending => ordering,
+                ZSortDirection::Descending => ordering.reverse(),
+            }
+        });
         uuid_zsorts.into_iter().map(|(uuid, _zsort)| uuid).collect()
     }
 
-    fn sort_by_zsort(&self, node: &dyn Node) -> Vec<NodeUuid> {
+    fn sort_by_zsort(&self, node: &dyn Node, direction: ZSortDirection) -> Vec<NodeUuid> {
         let uuid_zsorts = self.rec_zsorts_from_root(node, 0.);
-        self.sort_uuids_by_zsort(uuid_zsorts)
+        self.sort_uuids_by_zsort(uuid_zsorts, direction)
     }
 
+    /// Nodes in drawing order, highest zsort first.
     pub fn zsorted(&self) -> Vec<NodeUuid> {
+        self.zsorted_by(ZSortDirection::default())
+    }
+
+    /// Nodes sorted by zsort in the given direction.
+    ///
+    /// Nodes with equal zsorts keep their tree order in both directions.
+    pub fn zsorted_by(&self, direction: ZSortDirection) -> Vec<NodeUuid> {
         let root = self.arena.get(self.root).unwrap().get();
-        self.sort_by_zsort(root.as_ref())
+        self.sort_by_zsort(root.as_ref(), direction)
+    }
+
+    /// Drawables a composite draws into its own buffer, in drawing order, highest zsort first.
+    ///
+    /// Like [`NodeTree::zsorted`], but rooted at the composite: zsorts accumulate from its children,
+    /// and the children of nested composites are left to them.
+    /// Returns an empty list if there is no composite with that UUID.
+    pub fn composite_render_list(&self, uuid: NodeUuid) -> Vec<NodeUuid> {
+        if self.get_node(uuid).map(|node| node.kind()) != Some(NodeKind::Composite) {
+            return Vec::new();
+        }
+
+        let uuid_zsorts = self
+            .get_children_uuids(uuid)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|child| self.get_node(child))
+            .flat_map(|child| self.rec_zsorts_from_root(child.as_ref(), 0.))
+            .filter(|&(uuid, _)| self.drawable(uuid).is_ok())
+            .collect();
+        self.sort_uuids_by_zsort(uuid_zsorts, ZSortDirection::Descending)
+    }
+}
+
+fn rec_eq(
+    a: NodeId,
+    a_arena: &Arena<Box<dyn Node>>,
+    b: NodeId,
+    b_arena: &Arena<Box<dyn Node>>,
+) -> bool {
+    let (Some(a_node), Some(b_node)) = (a_arena.get(a), b_arena.get(b)) else {
+        return false;
+    };
+    if !a_node.get().dyn_eq(b_node.get().as_ref()) {
+        return false;
+    }
+
+    let mut a_children = a.children(a_arena);
+    let mut b_children = b.children(b_arena);
+    loop {
+        match (a_children.next(), b_children.next()) {
+            (None, None) => return true,
+            (Some(a), Some(b)) if rec_eq(a, a_arena, b, b_arena) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Trees are equal when they have the same structure and equal nodes,
+/// regardless of where the nodes are stored in the arena.
+impl PartialEq for NodeTree {
+    fn eq(&self, other: &Self) -> bool {
+        rec_eq(self.root, &self.arena, other.root, &other.arena)
     }
 }
 
@@ -144,7 +855,7 @@ fn rec_fmt(
 
     let node = node.get();
 
-    let type_name = node.typetag_name();
+    let type_name = node.node_type_name();
     #[cfg(feature = "owo")]
     let type_name = {
         use owo_colors::OwoColorize;
@@ -173,7 +884,7 @@ impl Display for NodeTree {
 
         let root_node = root_node.get();
 
-        let type_name = root_node.typetag_name();
+        let type_name = root_node.node_type_name();
         #[cfg(feature = "owo")]
         let type_name = {
             use owo_colors::OwoColorize;
@@ -189,6 +900,11 @@ impl Display for NodeTree {
     }
 }
 
+/// Only the nodes still linked to the root are serialized, so removed nodes are left out.
+///
+/// Children are written in order and read back in that order, so sibling order,
+/// and with it [`NodeTree::zsorted`] for nodes with equal zsorts, survives a round trip.
+#[cfg(feature = "serde")]
 impl Serialize for NodeTree {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -198,6 +914,7 @@ impl Serialize for NodeTree {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for NodeTree {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -208,11 +925,203 @@ impl<'de> Deserialize<'de> for NodeTree {
     }
 }
 
+#[cfg(feature = "serde")]
 impl From<SNodeTree> for NodeTree {
     fn from(sntree: SNodeTree) -> Self {
-        let mut arena = Arena::new();
+        let mut arena = SPARE_ARENA.take().unwrap_or_default();
         let mut uuids = BTreeMap::new();
         let root = sntree.flatten_into(&mut arena, &mut uuids);
-        NodeTree { root, arena, uuids }
+        NodeTree {
+            root,
+            arena,
+            uuids,
+            scale: 1.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    use glam::Vec3;
+
+    use super::*;
+    use crate::nodes::node::NodeState;
+
+    fn tree(zsorts: &[f32]) -> NodeTree {
+        let mut tree = NodeTree::new(Box::new(NodeState::new(NodeUuid(0), "Root")));
+        for (i, &zsort) in zsorts.iter().enumerate() {
+            let mut state = NodeState::new(NodeUuid(i as u32 + 1), format!("Node {i}"));
+            state.zsort = zsort;
+            tree.add_node(NodeUuid(0), Box::new(state)).unwrap();
+        }
+        tree
+    }
+
+    #[cfg(feature = "serde")]
+    fn node(uuid: u32, translation: [f32; 3], rotation: f32) -> serde_json::Value {
+        serde_json::json!({
+            "type": "Node",
+            "uuid": uuid,
+            "name": format!("Node {uuid}"),
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": translation, "rot": [0., 0., rotation], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "children": [],
+        })
+    }
+
+    /// Root moved by 1000 along X, with a bone rotated by 90° and an accessory hanging from it.
+    #[cfg(feature = "serde")]
+    fn rig(lock_to_root: bool) -> NodeTree {
+        let mut accessory = node(2, [5., 0., 0.], 0.);
+        accessory["lockToRoot"] = lock_to_root.into();
+        let mut bone = node(1, [10., 0., 0.], std::f32::consts::FRAC_PI_2);
+        bone["children"] = serde_json::json!([accessory]);
+        let mut root = node(0, [1000., 0., 0.], 0.);
+        root["children"] = serde_json::json!([bone]);
+        serde_json::from_value(root).unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn lock_to_root_skips_parent_transforms() {
+        let world = rig(false).world_transform(NodeUuid(2)).unwrap();
+        assert!(world
+            .translation
+            .abs_diff_eq(Vec3::new(1010., 5., 0.), 1e-4));
+        assert!((world.rotation.z - std::f32::consts::FRAC_PI_2).abs() < 1e-4);
+
+        let world = rig(true).world_transform(NodeUuid(2)).unwrap();
+        assert!(world
+            .translation
+            .abs_diff_eq(Vec3::new(1005., 0., 0.), 1e-4));
+        assert!(world.rotation.z.abs() < 1e-4);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parts_rotate_around_their_origin() {
+        let mut group = node(1, [50., 0., 0.], 0.);
+        group["children"] = serde_json::json!([{
+            "type": "Part",
+            "uuid": 2,
+            "name": "Part",
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., std::f32::consts::FRAC_PI_2], "scale": [2., 2.] },
+            "lockToRoot": false,
+            "blend_mode": "Normal",
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+            "opacity": 1.,
+            "mesh": { "verts": [8., 4., 4., 4.], "uvs": [0., 0., 0., 0.], "indices": [], "origin": [4., 4.] },
+            "textures": [0, 0, 0],
+            "children": [],
+        }]);
+        let mut root = node(0, [0., 0., 0.], 0.);
+        root["children"] = serde_json::json!([group]);
+        let tree: NodeTree = serde_json::from_value(root).unwrap();
+
+        let verts = tree.baked_verts(NodeUuid(2)).unwrap();
+        // 4 pixels right of the origin, doubled and turned a quarter towards +Y (down in Inochi2D)
+        assert!(verts[0].abs_diff_eq(Vec2::new(54., 12.), 1e-4));
+        // The origin stays in place
+        assert!(verts[1].abs_diff_eq(Vec2::new(54., 4.), 1e-4));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn composite_children_are_sorted_by_the_composite() {
+        let drawable = |kind: &str, uuid, zsort: f32| {
+            let mut node = node(uuid, [0., 0., 0.], 0.);
+            node["type"] = kind.into();
+            node["zsort"] = zsort.into();
+            node["blend_mode"] = "Normal".into();
+            node["tint"] = serde_json::json!([1., 1., 1.]);
+            node["screenTint"] = serde_json::json!([0., 0., 0.]);
+            node["mask_threshold"] = 0.5.into();
+            node["opacity"] = 1.0.into();
+            if kind == "Part" {
+                node["mesh"] = serde_json::json!({ "verts": [], "uvs": [], "indices": [], "origin": [0., 0.] });
+                node["textures"] = serde_json::json!([0, 0, 0]);
+            }
+            node
+        };
+        let mut group = node(1, [0., 0., 0.], 0.);
+        // A plain node's children are sorted with the rest of the puppet
+        group["children"] = serde_json::json!([drawable("Part", 5, 3.)]);
+        let mut composite = drawable("Composite", 2, 0.);
+        composite["children"] =
+            serde_json::json!([drawable("Part", 3, 1.), drawable("Part", 4, 2.)]);
+        let mut root = node(0, [0., 0., 0.], 0.);
+        root["children"] = serde_json::json!([group, composite]);
+        let tree: NodeTree = serde_json::from_value(root).unwrap();
+
+        assert_eq!(tree.zsorted(), [5, 0, 1, 2].map(NodeUuid));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn removed_subtrees_arent_serialized() {
+        fn uuids(value: &serde_json::Value, out: &mut Vec<u64>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    out.extend(map.get("uuid").and_then(|uuid| uuid.as_u64()));
+                    map.values().for_each(|value| uuids(value, out));
+                }
+                serde_json::Value::Array(values) => {
+                    values.iter().for_each(|value| uuids(value, out))
+                }
+                _ => {}
+            }
+        }
+
+        let mut child = node(4, [0., 0., 0.], 0.);
+        child["children"] = serde_json::json!([node(5, [0., 0., 0.], 0.)]);
+        let mut removed = node(2, [0., 0., 0.], 0.);
+        removed["children"] = serde_json::json!([child]);
+        let mut root = node(0, [0., 0., 0.], 0.);
+        root["children"] = serde_json::json!([
+            node(1, [0., 0., 0.], 0.),
+            removed,
+            node(3, [0., 0., 0.], 0.)
+        ]);
+        let mut tree: NodeTree = serde_json::from_value(root).unwrap();
+
+        assert!(tree.remove_subtree(NodeUuid(0)).is_empty());
+        assert_eq!(tree.remove_subtree(NodeUuid(2)), [2, 4, 5].map(NodeUuid));
+        assert!(tree.remove_subtree(NodeUuid(2)).is_empty());
+
+        let json = serde_json::to_value(&tree).unwrap();
+        let mut serialized = Vec::new();
+        uuids(&json, &mut serialized);
+        assert_eq!(serialized, [0, 1, 3]);
+
+        let tree: NodeTree = serde_json::from_value(json).unwrap();
+        let present: Vec<_> = (0..6)
+            .filter(|&uuid| tree.get_node(NodeUuid(uuid)).is_some())
+            .collect();
+        assert_eq!(present, [0, 1, 3]);
+    }
+
+    #[test]
+    fn nan_zsort_does_not_panic() {
+        let mut tree = tree(&[1., 2.]);
+        assert!(tree.set_zsort_override(NodeUuid(1), Some(f32::NAN)));
+
+        let zsorted = tree.zsorted_by(ZSortDirection::Ascending);
+        assert_eq!(zsorted, [0, 2, 1].map(NodeUuid));
+        assert_eq!(tree.zsorted(), [1, 2, 0].map(NodeUuid));
+    }
+
+    #[test]
+    fn signed_zeroes_keep_tree_order() {
+        let tree = tree(&[0., -0., 0.]);
+        let expected = [0, 1, 2, 3].map(NodeUuid);
+        assert_eq!(tree.zsorted_by(ZSortDirection::Ascending), expected);
+        assert_eq!(tree.zsorted_by(ZSortDirection::Descending), expected);
     }
 }
@@ -1,29 +1,183 @@
+use glam::{Mat4, Vec2};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::impl_node;
+use crate::math::hull::convex_hull;
+use crate::math::transform::{snap_to_pixels, transform_points, Transform};
 use crate::mesh::Mesh;
 
-use super::drawable::Drawable;
+use super::drawable::{DrawState, Drawable};
 use super::node::NodeState;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Part {
+/// Custom material a part asks to be drawn with, for renderers that have their own shaders.
+///
+/// Inochi2D doesn't define any, so renderers that don't know `id` should use their default part shader.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MaterialRef {
+    pub id: String,
+    /// Settings of the material, kept so that they survive a round trip.
+    #[cfg(feature = "serde")]
     #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Part {
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub node_state: NodeState,
-    #[serde(flatten)]
-    pub draw_state: Drawable,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub draw_state: DrawState,
     pub mesh: Mesh,
     pub textures: [usize; 3],
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub material: Option<MaterialRef>,
+    /// Per-vertex offsets set by deform bindings.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub deform: Vec<Vec2>,
     #[cfg(feature = "opengl")]
-    #[serde(skip)]
-    pub start_indice: u16,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub start_indice: u32,
     // start_deform: u16,
 }
 
-impl_node!(Part, node_state);
+impl_node!(Part, node_state, Part, {
+    fn as_drawable(&self) -> Option<&dyn Drawable> {
+        Some(self)
+    }
+});
+
+impl Drawable for Part {
+    fn draw_state(&self) -> &DrawState {
+        &self.draw_state
+    }
+
+    fn mesh(&self) -> Option<&Mesh> {
+        Some(&self.mesh)
+    }
+
+    fn texture_indices(&self) -> &[usize] {
+        &self.textures
+    }
+}
 
 impl Part {
-    pub(crate) fn num_indices(&self) -> u16 {
-        self.mesh.indices.len() as u16
+    /// Makes a part with a default [`DrawState`].
+    pub fn new(node_state: NodeState, mesh: Mesh, textures: [usize; 3]) -> Self {
+        Self {
+            node_state,
+            draw_state: DrawState::default(),
+            mesh,
+            textures,
+            material: None,
+            deform: Vec::new(),
+            #[cfg(feature = "opengl")]
+            start_indice: 0,
+        }
+    }
+
+    #[cfg(feature = "opengl")]
+    pub(crate) fn num_indices(&self) -> u32 {
+        self.mesh.indices.len() as u32
+    }
+
+    /// Final position of the vertices of the part, to export the current pose.
+    ///
+    /// `parent` is the world transform of the part's parent, or of the root if the part is locked to it,
+    /// with its matrix up to date (see [`Transform::update`]). For each vertex, in this order:
+    ///
+    /// 1. its deform offset is added,
+    /// 2. the part's own transform is applied around the mesh origin,
+    ///    which is the pivot of the rotation and scale (see [`Transform::matrix_around`]),
+    /// 3. the `parent` matrix is applied.
+    ///
+    /// [`NodeTree::baked_verts`](super::node_tree::NodeTree::baked_verts) finds the parent by itself.
+    pub fn baked_verts(&self, parent: &Transform) -> Vec<Vec2> {
+        let mut verts = Vec::new();
+        self.bake_verts_into(parent.matrix(), &mut verts);
+        verts
+    }
+
+    /// Matrix mapping the vertices of the part to the space `parent` maps to.
+    pub(crate) fn matrix(&self, parent: Mat4) -> Mat4 {
+        let local = self.node_state.local_transform();
+        let matrix = parent * local.matrix_around(self.mesh.origin);
+        if local.pixel_snap {
+            snap_to_pixels(matrix)
+        } else {
+            matrix
+        }
+    }
+
+    /// Appends the vertices of [`Part::baked_verts`] to `out`, with `parent` being the parent's matrix.
+    pub(crate) fn bake_verts_into(&self, parent: Mat4, out: &mut Vec<Vec2>) {
+        transform_points(self.matrix(parent), &self.mesh.vertices, &self.deform, out);
+    }
+
+    /// Convex hull of the rest vertices of the mesh, ignoring deforms and transforms,
+    /// see [`convex_hull`].
+    pub fn convex_hull(&self) -> Vec<Vec2> {
+        convex_hull(&self.mesh.vertices)
+    }
+
+    /// Clears the deform offsets, making sure there is one per vertex.
+    pub fn reset_deform(&mut self) {
+        self.deform.clear();
+        self.deform.resize(self.mesh.vertices.len(), Vec2::ZERO);
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::{vec2, vec3};
+
+    use super::*;
+
+    /// Part with a vertex right of its origin, and one on it.
+    fn part() -> Part {
+        serde_json::from_value(serde_json::json!({
+            "uuid": 1,
+            "name": "Part",
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "blend_mode": "Normal",
+            "tint": [1., 1., 1.],
+            "screenTint": [0., 0., 0.],
+            "mask_threshold": 0.5,
+            "opacity": 1.,
+            "mesh": {
+                "verts": [10., 0., 5., 0.],
+                "uvs": [0., 0., 0., 0.],
+                "indices": [],
+                "origin": [5., 0.],
+            },
+            "textures": [0, 0, 0],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn baked_verts_deform_then_transform_around_origin() {
+        let mut part = part();
+        part.deform = vec![vec2(0., 1.)];
+        part.node_state.transform.translation = vec3(0., 10., 0.);
+        part.node_state.transform.rotation = vec3(0., 0., FRAC_PI_2);
+        let mut parent = Transform::new().with_translation(vec3(100., 0., 0.));
+        parent.update();
+        let verts = part.baked_verts(&parent);
+
+        // (10, 0) is deformed to (10, 1), rotated around the origin to (4, 5), then translated
+        assert!(verts[0].abs_diff_eq(vec2(104., 15.), 1e-4));
+        // The origin itself, without deform, only moves with the translations
+        assert!(verts[1].abs_diff_eq(vec2(105., 10.), 1e-4));
     }
 }
@@ -0,0 +1,205 @@
+use std::fmt::Display;
+
+use super::node::{NodeState, NodeUuid};
+use super::node_tree::NodeTree;
+
+/// Change to the state of a node that exists in both trees.
+#[derive(Clone, Debug)]
+pub enum StateChange {
+    Renamed {
+        from: String,
+        to: String,
+    },
+    /// The node's type changed, e.g. a part was turned into a mask.
+    Retyped {
+        from: &'static str,
+        to: &'static str,
+    },
+    Enabled(bool),
+    Zsort {
+        from: f32,
+        to: f32,
+    },
+    /// The node's translation, rotation, scale or pixel snapping changed.
+    Transform,
+    LockToRoot(bool),
+}
+
+impl Display for StateChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateChange::Renamed { from, to } => write!(f, "renamed from {from:?} to {to:?}"),
+            StateChange::Retyped { from, to } => write!(f, "type changed from {from} to {to}"),
+            StateChange::Enabled(true) => write!(f, "enabled"),
+            StateChange::Enabled(false) => write!(f, "disabled"),
+            StateChange::Zsort { from, to } => write!(f, "zsort changed from {from} to {to}"),
+            StateChange::Transform => write!(f, "transform changed"),
+            StateChange::LockToRoot(true) => write!(f, "locked to root"),
+            StateChange::LockToRoot(false) => write!(f, "unlocked from root"),
+        }
+    }
+}
+
+/// Node that was moved to another parent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reparent {
+    pub node: NodeUuid,
+    pub from: Option<NodeUuid>,
+    pub to: Option<NodeUuid>,
+}
+
+/// Node whose state changed.
+#[derive(Clone, Debug)]
+pub struct NodeChange {
+    pub node: NodeUuid,
+    pub changes: Vec<StateChange>,
+}
+
+/// Differences between two node trees, see [`NodeTree::diff`].
+///
+/// Nodes are matched by UUID, and every list is sorted by UUID.
+#[derive(Clone, Debug, Default)]
+pub struct TreeDiff {
+    /// Nodes that are only in the new tree.
+    pub added: Vec<NodeUuid>,
+    /// Nodes that are only in the old tree.
+    pub removed: Vec<NodeUuid>,
+    pub reparented: Vec<Reparent>,
+    pub changed: Vec<NodeChange>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.reparented.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+fn state_changes(old: &NodeState, new: &NodeState) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+    if old.name != new.name {
+        changes.push(StateChange::Renamed {
+            from: old.name.clone(),
+            to: new.name.clone(),
+        });
+    }
+    if old.enabled != new.enabled {
+        changes.push(StateChange::Enabled(new.enabled));
+    }
+    if old.zsort != new.zsort {
+        changes.push(StateChange::Zsort {
+            from: old.zsort,
+            to: new.zsort,
+        });
+    }
+    let (a, b) = (&old.transform, &new.transform);
+    if a.translation != b.translation
+        || a.rotation != b.rotation
+        || a.scale != b.scale
+        || a.pixel_snap != b.pixel_snap
+    {
+        changes.push(StateChange::Transform);
+    }
+    if old.lock_to_root != new.lock_to_root {
+        changes.push(StateChange::LockToRoot(new.lock_to_root));
+    }
+    changes
+}
+
+impl NodeTree {
+    fn parent_uuid(&self, uuid: NodeUuid) -> Option<NodeUuid> {
+        self.get_parent(uuid)
+            .map(|parent| parent.get_node_state().uuid)
+    }
+
+    /// Compares this tree with a newer version of it.
+    pub fn diff(&self, other: &NodeTree) -> TreeDiff {
+        let mut diff = TreeDiff::default();
+
+        for (&uuid, _) in self.uuids.iter() {
+            if !other.uuids.contains_key(&uuid) {
+                diff.removed.push(uuid);
+            }
+        }
+
+        for (&uuid, _) in other.uuids.iter() {
+            let (Some(old), Some(new)) = (self.get_node(uuid), other.get_node(uuid)) else {
+                diff.added.push(uuid);
+                continue;
+            };
+
+            let (from, to) = (self.parent_uuid(uuid), other.parent_uuid(uuid));
+            if from != to {
+                diff.reparented.push(Reparent {
+                    node: uuid,
+                    from,
+                    to,
+                });
+            }
+
+            let mut changes = Vec::new();
+            if old.node_type_name() != new.node_type_name() {
+                changes.push(StateChange::Retyped {
+                    from: old.node_type_name(),
+                    to: new.node_type_name(),
+                });
+            }
+            changes.extend(state_changes(old.get_node_state(), new.get_node_state()));
+            if !changes.is_empty() {
+                diff.changed.push(NodeChange {
+                    node: uuid,
+                    changes,
+                });
+            }
+        }
+
+        diff
+    }
+}
+
+fn fmt_parent(parent: Option<NodeUuid>) -> String {
+    match parent {
+        Some(uuid) => uuid.as_u32().to_string(),
+        None => "(none)".to_owned(),
+    }
+}
+
+impl Display for TreeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        let (added, removed, changed) = ("+", "-", "~");
+        #[cfg(feature = "owo")]
+        let (added, removed, changed) = {
+            use owo_colors::OwoColorize;
+            (added.green(), removed.red(), changed.yellow())
+        };
+
+        for uuid in &self.added {
+            writeln!(f, "{added} node {}", uuid.as_u32())?;
+        }
+        for uuid in &self.removed {
+            writeln!(f, "{removed} node {}", uuid.as_u32())?;
+        }
+        for reparent in &self.reparented {
+            writeln!(
+                f,
+                "{changed} node {}: moved from parent {} to {}",
+                reparent.node.as_u32(),
+                fmt_parent(reparent.from),
+                fmt_parent(reparent.to)
+            )?;
+        }
+        for node_change in &self.changed {
+            for change in &node_change.changes {
+                writeln!(f, "{changed} node {}: {change}", node_change.node.as_u32())?;
+            }
+        }
+
+        Ok(())
+    }
+}
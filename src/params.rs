@@ -0,0 +1,586 @@
+//! Parameters, and the bindings through which they drive nodes.
+//!
+//! When several bindings drive the same field of a node, whether they come from one or several
+//! parameters, their values are combined rather than the last one winning:
+//!
+//! - translations, rotations, zsorts and deforms are summed,
+//! - scales and opacities are multiplied.
+//!
+//! Both operations are commutative, so the result doesn't depend on the order of the bindings.
+//!
+//! Bindings with [`MergeMode::Override`] replace the offset of their field instead.
+//! They are applied after all the additive bindings driving the same nodes, so they win over them
+//! whatever the order of the parameters. If several of them drive the same field,
+//! the one of the last parameter of the puppet wins.
+//!
+//! Bindings are offsets on top of the node's own state, which is left untouched.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use glam::Vec2;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::nodes::node::NodeUuid;
+use crate::nodes::node_tree::NodeTree;
+use crate::nodes::part::Part;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(transparent)]
+pub struct ParamUuid(pub(crate) u32);
+
+impl ParamUuid {
+    /// Makes a UUID from its raw value, as stored in puppet files.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// Raw value of the UUID.
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InterpolateMode {
+    Linear,
+}
+
+/// How a binding combines with the others driving the same field, see the [module docs](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MergeMode {
+    /// Sums or multiplies with the other bindings.
+    #[default]
+    Additive,
+    /// Replaces what the other bindings did.
+    #[cfg_attr(feature = "serde", serde(alias = "Forced"))]
+    Override,
+}
+
+impl MergeMode {
+    #[cfg(feature = "serde")]
+    fn is_additive(&self) -> bool {
+        *self == MergeMode::Additive
+    }
+
+    /// Merges a summed field with a value, if there is one.
+    fn sum(self, field: &mut f32, value: Option<f32>) {
+        let Some(value) = value else {
+            return;
+        };
+        match self {
+            MergeMode::Additive => *field += value,
+            MergeMode::Override => *field = value,
+        }
+    }
+
+    /// Merges a multiplied field with a value, if there is one.
+    fn product(self, field: &mut f32, value: Option<f32>) {
+        let Some(value) = value else {
+            return;
+        };
+        match self {
+            MergeMode::Additive => *field *= value,
+            MergeMode::Override => *field = value,
+        }
+    }
+
+    fn sum_vec(self, field: &mut Vec2, value: Vec2) {
+        match self {
+            MergeMode::Additive => *field += value,
+            MergeMode::Override => *field = value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BindingBase {
+    pub node: NodeUuid,
+    #[cfg_attr(feature = "serde", serde(rename = "isSet"))]
+    pub is_set: Vec<Vec<bool>>,
+    pub interpolate_mode: InterpolateMode,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            alias = "mergeMode",
+            skip_serializing_if = "MergeMode::is_additive"
+        )
+    )]
+    pub merge_mode: MergeMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "param_name"))]
+pub enum Binding {
+    #[cfg_attr(feature = "serde", serde(rename = "zSort"))]
+    ZSort {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.t.x"))]
+    TransformTX {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.t.y"))]
+    TransformTY {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.s.x"))]
+    TransformSX {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.s.y"))]
+    TransformSY {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.r.x"))]
+    TransformRX {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.r.y"))]
+    TransformRY {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "transform.r.z"))]
+    TransformRZ {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "opacity"))]
+    Opacity {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<f32>>,
+    },
+    #[cfg_attr(feature = "serde", serde(rename = "deform"))]
+    Deform {
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        base: BindingBase,
+        values: Vec<Vec<Vec<Vec2>>>,
+    },
+}
+
+impl Binding {
+    pub fn base(&self) -> &BindingBase {
+        match self {
+            Binding::ZSort { base, .. }
+            | Binding::TransformTX { base, .. }
+            | Binding::TransformTY { base, .. }
+            | Binding::TransformSX { base, .. }
+            | Binding::TransformSY { base, .. }
+            | Binding::TransformRX { base, .. }
+            | Binding::TransformRY { base, .. }
+            | Binding::TransformRZ { base, .. }
+            | Binding::Opacity { base, .. }
+            | Binding::Deform { base, .. } => base,
+        }
+    }
+
+    /// UUID of the node this binding drives.
+    pub fn node(&self) -> NodeUuid {
+        self.base().node
+    }
+
+    pub fn merge_mode(&self) -> MergeMode {
+        self.base().merge_mode
+    }
+
+    /// Number of keypoints in each column of the binding's grid, i.e. for each X axis point.
+    ///
+    /// For a well formed binding, there is one column per X axis point of its parameter
+    /// and each of them has one keypoint per Y axis point.
+    pub fn grid_columns(&self) -> Vec<usize> {
+        match self {
+            Binding::ZSort { values, .. }
+            | Binding::TransformTX { values, .. }
+            | Binding::TransformTY { values, .. }
+            | Binding::TransformSX { values, .. }
+            | Binding::TransformSY { values, .. }
+            | Binding::TransformRX { values, .. }
+            | Binding::TransformRY { values, .. }
+            | Binding::TransformRZ { values, .. }
+            | Binding::Opacity { values, .. } => values.iter().map(Vec::len).collect(),
+            Binding::Deform { values, .. } => values.iter().map(Vec::len).collect(),
+        }
+    }
+
+    /// Interpolates a binding driving a single value at `(x, y)`.
+    ///
+    /// Returns `None` if there are no keypoints, or if the result isn't finite because of a corrupt keypoint,
+    /// so that it leaves the node untouched instead of poisoning it and all its children.
+    fn sample(&self, values: &[Vec<f32>], x: AxisPos, y: AxisPos) -> Option<f32> {
+        let value = interpolate(values, x, y, lerp_f32)?;
+        if value.is_finite() {
+            return Some(value);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(node = ?self.node(), "Ignoring non-finite binding value {value}");
+        None
+    }
+
+    /// Interpolates the binding at `(x, y)` and combines the result with the node's offsets,
+    /// see the [module docs](self) for how.
+    fn apply(&self, nodes: &mut NodeTree, x: AxisPos, y: AxisPos) {
+        let mode = self.merge_mode();
+        if let Binding::Opacity { values, .. } = self {
+            if let Ok(draw_state) = nodes.drawable_mut(self.node()) {
+                mode.product(&mut draw_state.offset_opacity, self.sample(values, x, y));
+            }
+            return;
+        }
+
+        let Some(node) = nodes.get_node_mut(self.node()) else {
+            return;
+        };
+
+        if let Binding::Deform { values, .. } = self {
+            let Some(part) = node.as_any_mut().downcast_mut::<Part>() else {
+                return;
+            };
+            let Some(deform) = interpolate(values, x, y, lerp_vecs) else {
+                return;
+            };
+            for (offset, delta) in part.deform.iter_mut().zip(deform) {
+                if delta.is_finite() {
+                    mode.sum_vec(offset, delta);
+                } else {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(node = ?self.node(), "Ignoring non-finite deform {delta}");
+                }
+            }
+            return;
+        }
+
+        let state = node.get_node_state_mut();
+        let offset = &mut state.offset_transform;
+        match self {
+            Binding::ZSort { values, .. } => {
+                mode.sum(&mut state.offset_zsort, self.sample(values, x, y));
+            }
+            Binding::TransformTX { values, .. } => {
+                mode.sum(&mut offset.translation.x, self.sample(values, x, y));
+            }
+            Binding::TransformTY { values, .. } => {
+                mode.sum(&mut offset.translation.y, self.sample(values, x, y));
+            }
+            Binding::TransformSX { values, .. } => {
+                mode.product(&mut offset.scale.x, self.sample(values, x, y));
+            }
+            Binding::TransformSY { values, .. } => {
+                mode.product(&mut offset.scale.y, self.sample(values, x, y));
+            }
+            Binding::TransformRX { values, .. } => {
+                mode.sum(&mut offset.rotation.x, self.sample(values, x, y));
+            }
+            Binding::TransformRY { values, .. } => {
+                mode.sum(&mut offset.rotation.y, self.sample(values, x, y));
+            }
+            Binding::TransformRZ { values, .. } => {
+                mode.sum(&mut offset.rotation.z, self.sample(values, x, y));
+            }
+            Binding::Opacity { .. } | Binding::Deform { .. } => unreachable!(),
+        }
+    }
+}
+
+/// Position of a parameter value between two keypoints of one axis.
+#[derive(Clone, Copy, Debug)]
+struct AxisPos {
+    /// Index of the keypoint right before the value.
+    index: usize,
+    /// How far the value is between that keypoint and the next one.
+    t: f32,
+}
+
+impl AxisPos {
+    fn new(axis_points: &[f32], value: f32) -> Self {
+        if axis_points.len() < 2 {
+            return Self { index: 0, t: 0. };
+        }
+
+        let index = axis_points
+            .windows(2)
+            .position(|w| value <= w[1])
+            .unwrap_or(axis_points.len() - 2);
+        let (start, end) = (axis_points[index], axis_points[index + 1]);
+        let t = if end > start {
+            ((value - start) / (end - start)).clamp(0., 1.)
+        } else {
+            0.
+        };
+
+        Self { index, t }
+    }
+}
+
+fn lerp_f32(a: &f32, b: &f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[allow(clippy::ptr_arg)]
+fn lerp_vecs(a: &Vec<Vec2>, b: &Vec<Vec2>, t: f32) -> Vec<Vec2> {
+    a.iter().zip(b).map(|(a, b)| a.lerp(*b, t)).collect()
+}
+
+/// Bilinear interpolation over a `values[x][y]` keypoint grid.
+fn interpolate<T>(
+    values: &[Vec<T>],
+    x: AxisPos,
+    y: AxisPos,
+    lerp: impl Fn(&T, &T, f32) -> T,
+) -> Option<T> {
+    let (col0, col1) = (clamped(values, x.index)?, clamped(values, x.index + 1)?);
+    let bottom = lerp(clamped(col0, y.index)?, clamped(col1, y.index)?, x.t);
+    let top = lerp(
+        clamped(col0, y.index + 1)?,
+        clamped(col1, y.index + 1)?,
+        x.t,
+    );
+    Some(lerp(&bottom, &top, y.t))
+}
+
+/// Gets the element at `index`, or the last one if it's out of bounds.
+fn clamped<T>(slice: &[T], index: usize) -> Option<&T> {
+    slice.get(index).or_else(|| slice.last())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Param {
+    pub uuid: ParamUuid,
+    pub name: String,
+    pub is_vec2: bool,
+    pub min: Vec2,
+    pub max: Vec2,
+    pub defaults: Vec2,
+    pub axis_points: [Vec<f32>; 2],
+    pub bindings: Vec<Binding>,
+    /// Current value, `None` until it's first set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    value: Option<Vec2>,
+}
+
+impl Param {
+    /// Makes a parameter without bindings, defaulting to the center of its range,
+    /// with axis points only at its ends.
+    ///
+    /// The Y axis of a 1D parameter only has one point.
+    pub fn new(
+        uuid: ParamUuid,
+        name: impl Into<String>,
+        is_vec2: bool,
+        min: Vec2,
+        max: Vec2,
+    ) -> Self {
+        let y_points = if is_vec2 { vec![0., 1.] } else { vec![0.] };
+        Self {
+            uuid,
+            name: name.into(),
+            is_vec2,
+            min,
+            max,
+            defaults: (min + max) / 2.,
+            axis_points: [vec![0., 1.], y_points],
+            bindings: Vec::new(),
+            value: None,
+        }
+    }
+
+    /// Current value of the parameter, which is its default until it's set.
+    pub fn value(&self) -> Vec2 {
+        self.value.unwrap_or(self.defaults)
+    }
+
+    /// Sets the value of the parameter, clamped to its range.
+    ///
+    /// This doesn't apply the bindings, see [`Puppet::set_param`](crate::puppet::Puppet::set_param).
+    pub fn set_value(&mut self, value: Vec2) {
+        self.value = Some(value.clamp(self.min, self.max));
+    }
+
+    /// Value of the parameter mapped to the `[0, 1]` range of its axis points.
+    pub fn normalized_value(&self) -> Vec2 {
+        self.normalize(self.value())
+    }
+
+    fn normalize(&self, value: Vec2) -> Vec2 {
+        let range = self.max - self.min;
+        let value = (value - self.min) / range;
+        Vec2::select(range.cmpeq(Vec2::ZERO), Vec2::ZERO, value)
+    }
+
+    /// Offsets the deform bindings of this parameter give the vertices of their parts at `value`,
+    /// clamped to the parameter's range, summed per part.
+    ///
+    /// Non-finite offsets are zeroed, as they are ignored when the bindings are applied.
+    pub(crate) fn deforms_at(&self, value: Vec2) -> BTreeMap<NodeUuid, Vec<Vec2>> {
+        let value = self.normalize(value.clamp(self.min, self.max));
+        let x = AxisPos::new(&self.axis_points[0], value.x);
+        let y = AxisPos::new(&self.axis_points[1], value.y);
+
+        let mut deforms = BTreeMap::<_, Vec<Vec2>>::new();
+        for binding in &self.bindings {
+            let Binding::Deform { values, .. } = binding else {
+                continue;
+            };
+            let Some(deform) = interpolate(values, x, y, lerp_vecs) else {
+                continue;
+            };
+            let sum = deforms.entry(binding.node()).or_default();
+            if sum.len() < deform.len() {
+                sum.resize(deform.len(), Vec2::ZERO);
+            }
+            for (sum, delta) in sum.iter_mut().zip(deform) {
+                if delta.is_finite() {
+                    binding.merge_mode().sum_vec(sum, delta);
+                }
+            }
+        }
+        deforms
+    }
+
+    /// Applies the bindings of this parameter with that merge mode that drive one of the `targets`.
+    pub(crate) fn apply_bindings(
+        &self,
+        nodes: &mut NodeTree,
+        targets: &BTreeSet<NodeUuid>,
+        mode: MergeMode,
+    ) {
+        let value = self.normalized_value();
+        let x = AxisPos::new(&self.axis_points[0], value.x);
+        let y = AxisPos::new(&self.axis_points[1], value.y);
+
+        for binding in &self.bindings {
+            if binding.merge_mode() == mode && targets.contains(&binding.node()) {
+                binding.apply(nodes, x, y);
+            }
+        }
+    }
+}
+
+/// Values of all the parameters of a puppet, to save and restore poses.
+///
+/// See [`Puppet::snapshot`](crate::puppet::Puppet::snapshot).
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PoseSnapshot {
+    pub values: BTreeMap<ParamUuid, Vec2>,
+}
+
+/// What [`PoseSnapshot::lerp_with`] does with the parameters only one of the poses has.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum UnmatchedParams<'a> {
+    /// Leaves them out of the blend.
+    #[default]
+    Skip,
+    /// Keeps the value of the pose that has them.
+    Hold,
+    /// Blends them with their value in this pose, e.g. [`Puppet::default_pose`](crate::puppet::Puppet::default_pose),
+    /// as if the other pose had that value. They are left out if it doesn't have them either.
+    FadeTo(&'a PoseSnapshot),
+}
+
+impl PoseSnapshot {
+    /// Blends two poses, from `a` at `t = 0` to `b` at `t = 1`,
+    /// leaving out the parameters only one of them has.
+    ///
+    /// See [`PoseSnapshot::lerp_with`] to keep those.
+    pub fn lerp(a: &PoseSnapshot, b: &PoseSnapshot, t: f32) -> PoseSnapshot {
+        Self::lerp_with(a, b, t, UnmatchedParams::Skip)
+    }
+
+    /// Blends two poses like [`PoseSnapshot::lerp`], handling the parameters only one of them has with `unmatched`.
+    pub fn lerp_with(
+        a: &PoseSnapshot,
+        b: &PoseSnapshot,
+        t: f32,
+        unmatched: UnmatchedParams,
+    ) -> PoseSnapshot {
+        let uuids: BTreeSet<_> = a.values.keys().chain(b.values.keys()).collect();
+        let values = uuids
+            .into_iter()
+            .filter_map(|uuid| {
+                let value = match (a.values.get(uuid), b.values.get(uuid), unmatched) {
+                    (Some(a), Some(b), _) => a.lerp(*b, t),
+                    (Some(&value), None, UnmatchedParams::Hold)
+                    | (None, Some(&value), UnmatchedParams::Hold) => value,
+                    (Some(a), None, UnmatchedParams::FadeTo(defaults)) => {
+                        a.lerp(*defaults.values.get(uuid)?, t)
+                    }
+                    (None, Some(b), UnmatchedParams::FadeTo(defaults)) => {
+                        defaults.values.get(uuid)?.lerp(*b, t)
+                    }
+                    _ => return None,
+                };
+                Some((*uuid, value))
+            })
+            .collect();
+        PoseSnapshot { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::nodes::node::NodeState;
+
+    fn translate_x(node: u32, values: [f32; 2]) -> Binding {
+        Binding::TransformTX {
+            base: BindingBase {
+                node: NodeUuid(node),
+                is_set: vec![vec![true]; 2],
+                interpolate_mode: InterpolateMode::Linear,
+                merge_mode: MergeMode::Additive,
+            },
+            values: values.map(|value| vec![value]).to_vec(),
+        }
+    }
+
+    #[test]
+    fn nan_keypoint_only_affects_its_node() {
+        let root = NodeUuid(1);
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder
+            .add_node(root, NodeState::new(NodeUuid(2), "Bone"))
+            .unwrap()
+            .add_node(NodeUuid(2), NodeState::new(NodeUuid(3), "Child"))
+            .unwrap();
+        let mut param = Param::new(ParamUuid(1), "Param", false, Vec2::ZERO, Vec2::ONE);
+        param.bindings = vec![translate_x(2, [0., f32::NAN]), translate_x(3, [0., 10.])];
+        builder.add_param(param).unwrap();
+        let mut puppet = builder.build().puppet;
+
+        puppet.set_param(ParamUuid(1), Vec2::ONE);
+
+        let bone = puppet.nodes.get_node(NodeUuid(2)).unwrap();
+        assert_eq!(
+            bone.get_node_state().offset_transform.translation,
+            Vec3::ZERO
+        );
+        let child = puppet.nodes.world_transform(NodeUuid(3)).unwrap();
+        assert_eq!(child.translation, Vec3::new(10., 0., 0.));
+    }
+}
@@ -1,20 +1,64 @@
+use std::fmt::Display;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
 use image::ImageFormat;
 use nom::{
     bytes::complete::tag,
+    error::ErrorKind,
     multi::{length_data, length_value},
     number::complete::{be_u32, be_u8},
     IResult,
 };
 
-use crate::model::{Model, ModelTexture};
+use indextree::Arena;
+use serde::de::DeserializeOwned;
+
+use crate::model::{Model, ModelTexture, SharedModel, TextureStore};
+use crate::nodes::node::{Node, NodeUuid};
+use crate::nodes::node_tree::NodeTree;
+use crate::params::ParamUuid;
+use crate::puppet::{ParamConflict, Puppet, PuppetMeta, SpecVersion};
+
+use super::json::{self, JsonError};
+
+/// Oldest version of the Inochi2D spec this parser understands.
+pub const MIN_SUPPORTED_VERSION: SpecVersion = SpecVersion::new(1, 0);
+/// Newest version of the Inochi2D spec this parser understands.
+///
+/// Puppets made for a newer version are rejected, as their layout may have changed.
+pub const MAX_SUPPORTED_VERSION: SpecVersion = SpecVersion::new(1, 0);
+
+/// How deeply the arrays and objects of a puppet's JSON may nest by default, see [`ParseOptions::max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// Checks that the puppet was made for a version of the spec this parser understands.
+fn check_version(puppet: &Puppet) -> Result<(), InpParseError> {
+    check_meta_version(&puppet.meta)
+}
+
+fn check_meta_version(meta: &PuppetMeta) -> Result<(), InpParseError> {
+    match meta.spec_version() {
+        Some(version) if (MIN_SUPPORTED_VERSION..=MAX_SUPPORTED_VERSION).contains(&version) => {
+            Ok(())
+        }
+        _ => Err(InpParseError::UnsupportedVersion {
+            found: meta.version.clone(),
+        }),
+    }
+}
 
-fn parse_texture(i: &[u8]) -> IResult<&[u8], ModelTexture> {
-    let (i, format) = be_u8(i)?;
+fn parse_texture(input: &[u8]) -> IResult<&[u8], ModelTexture> {
+    let (i, format) = be_u8(input)?;
     let format = match format {
         0 => ImageFormat::Png,
         1 => ImageFormat::Tga,
-        2 => todo!("Unsupported format BC7"),
-        _ => todo!("Unknown format {format}!"),
+        // BC7 (2) isn't supported either
+        _ => {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                ErrorKind::Verify,
+            )))
+        }
     };
     let data = i.to_vec();
     Ok((b"", ModelTexture { format, data }))
@@ -24,14 +68,27 @@ fn parse_texture(i: &[u8]) -> IResult<&[u8], ModelTexture> {
 const MAGIC: &[u8] = b"TRNSRTS\0";
 /// Text section header
 const TEX: &[u8] = b"TEX_SECT";
-/// Extended section header
+// Extended section header
 // const EXT: &[u8] = b"EXT_SECT";
 
-fn be_u32_plus_1(i: &[u8]) -> IResult<&[u8], u32> {
-    let (i, int) = be_u32(i)?;
-    Ok((i, 1 + int))
+/// Length of a texture, which is followed by its format byte.
+fn be_u32_plus_1(input: &[u8]) -> IResult<&[u8], u32> {
+    let (i, int) = be_u32(input)?;
+    match int.checked_add(1) {
+        Some(len) => Ok((i, len)),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            ErrorKind::TooLarge,
+        ))),
+    }
 }
 
+/// Parses an `.inp` file.
+///
+/// Fails with [`ErrorKind::Verify`] if the puppet can't be deserialized,
+/// a texture isn't PNG or TGA,
+/// or the puppet was made for an unsupported version of the spec,
+/// see [`MIN_SUPPORTED_VERSION`] and [`MAX_SUPPORTED_VERSION`].
 pub fn parse_inp(i: &[u8]) -> IResult<&[u8], Model> {
     // TODO: proper error handling with nom? Currently really weird.
     let (i, _) = tag(MAGIC)(i)?;
@@ -46,11 +103,1069 @@ pub fn parse_inp(i: &[u8]) -> IResult<&[u8], Model> {
         i = i2;
     }
 
-    let puppet = match serde_json::from_slice(json) {
+    let puppet = match json::from_slice(json, DEFAULT_MAX_DEPTH) {
         Ok(puppet) => puppet,
-        Err(a) => {
-            panic!("{}", a);
+        Err(_error) => {
+            #[cfg(feature = "tracing")]
+            tracing::error!(
+                len = json.len(),
+                "Could not deserialize the puppet: {_error}"
+            );
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                json,
+                ErrorKind::Verify,
+            )));
         }
     };
+    if check_version(&puppet).is_err() {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            json,
+            ErrorKind::Verify,
+        )));
+    }
     Ok((i, Model { puppet, textures }))
 }
+
+/// Sections of an `.inp` file, in the order they appear in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InpSection {
+    /// Magic bytes and length of the puppet's JSON payload.
+    Header,
+    /// The puppet's JSON payload.
+    Puppet,
+    /// Texture section header and number of textures.
+    TextureHeader,
+    /// A texture, by index.
+    Texture(u32),
+}
+
+impl Display for InpSection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InpSection::Header => write!(f, "header"),
+            InpSection::Puppet => write!(f, "puppet payload"),
+            InpSection::TextureHeader => write!(f, "texture section header"),
+            InpSection::Texture(i) => write!(f, "data of texture {i}"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InpParseError {
+    #[error("Could not read the file: {0}")]
+    Io(#[from] io::Error),
+    #[error("The file is truncated: expected {expected} bytes in the {section}, got {got}")]
+    Truncated {
+        section: InpSection,
+        expected: u64,
+        got: u64,
+    },
+    #[error("The {section} declares {declared} bytes, but only {remaining} are left in the file")]
+    LengthExceedsInput {
+        section: InpSection,
+        declared: u64,
+        remaining: u64,
+    },
+    #[error("Not an .inp file (invalid magic bytes)")]
+    InvalidMagic,
+    #[error("Unsupported Inochi2D version {found:?}, only {MIN_SUPPORTED_VERSION} to {MAX_SUPPORTED_VERSION} are supported")]
+    UnsupportedVersion { found: String },
+    #[error("Could not deserialize the puppet: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The puppet nests more than {max_depth} levels deep")]
+    TooDeep { max_depth: usize },
+    #[error("Missing the texture section header")]
+    MissingTextureSection,
+    #[error("Unsupported texture format {0}")]
+    UnsupportedTextureFormat(u8),
+}
+
+impl From<JsonError> for InpParseError {
+    fn from(error: JsonError) -> Self {
+        match error {
+            JsonError::TooDeep { max_depth } => Self::TooDeep { max_depth },
+            JsonError::Json(error) => Self::Json(error),
+        }
+    }
+}
+
+/// Reader keeping track of how many bytes are left in the input, when it is known.
+struct Input<R> {
+    reader: R,
+    remaining: Option<u64>,
+}
+
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(read as u64);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Input<R> {
+    /// Skips `len` bytes without reading them, returning how many were skipped.
+    fn seek_forward(&mut self, len: u64) -> io::Result<u64> {
+        let offset = i64::try_from(len).map_err(|_| io::ErrorKind::InvalidInput)?;
+        self.reader.seek(SeekFrom::Current(offset))?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(len);
+        }
+        Ok(len)
+    }
+}
+
+impl<R> Input<R> {
+    /// Checks that there are at least `declared` bytes left, before trusting a length read from the file.
+    fn check_len(&self, declared: u64, section: InpSection) -> Result<(), InpParseError> {
+        match self.remaining {
+            Some(remaining) if declared > remaining => Err(InpParseError::LengthExceedsInput {
+                section,
+                declared,
+                remaining,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads as many bytes as possible into `buf`, returning how many were read.
+fn read_until_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+fn read_array<R: Read, const N: usize>(
+    reader: &mut R,
+    section: InpSection,
+) -> Result<[u8; N], InpParseError> {
+    let mut buf = [0; N];
+    let read = read_until_eof(reader, &mut buf)?;
+    if read != N {
+        return Err(InpParseError::Truncated {
+            section,
+            expected: N as u64,
+            got: read as u64,
+        });
+    }
+    Ok(buf)
+}
+
+fn read_u8<R: Read>(reader: &mut R, section: InpSection) -> Result<u8, InpParseError> {
+    Ok(read_array::<R, 1>(reader, section)?[0])
+}
+
+fn read_be_u32<R: Read>(reader: &mut R, section: InpSection) -> Result<u32, InpParseError> {
+    Ok(u32::from_be_bytes(read_array(reader, section)?))
+}
+
+/// Reads exactly `len` bytes.
+///
+/// The buffer grows as the data comes in, so a bogus length doesn't allocate up front.
+fn read_vec<R: Read>(
+    reader: &mut R,
+    len: usize,
+    section: InpSection,
+) -> Result<Vec<u8>, InpParseError> {
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(InpParseError::Truncated {
+            section,
+            expected: len as u64,
+            got: data.len() as u64,
+        });
+    }
+    Ok(data)
+}
+
+fn read_texture<R: Read>(reader: &mut Input<R>, index: u32) -> Result<ModelTexture, InpParseError> {
+    let section = InpSection::Texture(index);
+    let len = read_be_u32(reader, section)?;
+    // The format byte comes before the data
+    reader.check_len(len as u64 + 1, section)?;
+    let format = match read_u8(reader, section)? {
+        0 => ImageFormat::Png,
+        1 => ImageFormat::Tga,
+        format => return Err(InpParseError::UnsupportedTextureFormat(format)),
+    };
+    let data = read_vec(reader, len as usize, section)?;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(index, ?format, size = len, "Read texture");
+    Ok(ModelTexture { format, data })
+}
+
+/// Parses an `.inp` file from a reader.
+///
+/// Unlike [`parse_inp`], this doesn't need the whole file in memory:
+/// the puppet is deserialized straight from the reader.
+///
+/// Puppets made for a version of the spec outside of
+/// [`MIN_SUPPORTED_VERSION`]`..=`[`MAX_SUPPORTED_VERSION`] are rejected,
+/// and so are those nesting more than [`DEFAULT_MAX_DEPTH`] levels deep.
+pub fn parse_inp_reader<R: Read>(reader: R) -> Result<Model, InpParseError> {
+    let (puppet, textures) = parse_inp_with(reader, None, Vec::new(), |texture| texture)?;
+    Ok(Model { puppet, textures })
+}
+
+/// Parses an `.inp` file from a reader like [`parse_inp_reader`],
+/// but leaves the puppet as JSON, e.g. to edit fields that [`Puppet`] doesn't model.
+///
+/// The version of the spec isn't checked. See [`write_inp_raw`] to save it back:
+/// every field survives, but objects may come back with their keys in another order.
+pub fn parse_inp_raw<R: Read>(
+    reader: R,
+) -> Result<(serde_json::Value, Vec<ModelTexture>), InpParseError> {
+    parse_inp_json(
+        reader,
+        None,
+        DEFAULT_MAX_DEPTH,
+        None,
+        |_| Ok(()),
+        Vec::new(),
+        |texture| texture,
+    )
+}
+
+/// Optional processing done on puppets as they are loaded, see [`parse_inp_with_options`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Trims node and parameter names and strips the control characters in them, e.g. for display.
+    pub normalize_names: bool,
+    /// How deeply the arrays and objects of the puppet's JSON may nest before parsing fails
+    /// with [`InpParseError::TooDeep`], [`DEFAULT_MAX_DEPTH`] by default.
+    ///
+    /// Each level of the node tree takes two.
+    pub max_depth: usize,
+    /// Reads the `NaN`, `Infinity` and `-Infinity` some tools write in place of numbers as zeroes,
+    /// instead of failing with [`InpParseError::Json`], e.g. to repair hand-edited files.
+    ///
+    /// The whole JSON payload is then held in memory while it is parsed.
+    pub lenient_numbers: bool,
+    /// Drops the parameters whose UUID is already used by an earlier one,
+    /// see [`Puppet::drop_duplicate_params`].
+    pub drop_duplicate_params: bool,
+    /// Computes the [checksum](ModelTexture::checksum) of every texture into [`ParseReport::texture_checksums`],
+    /// to compare them with [`Model::verify_textures`].
+    pub texture_checksums: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            normalize_names: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            lenient_numbers: false,
+            drop_duplicate_params: false,
+            texture_checksums: false,
+        }
+    }
+}
+
+/// What [`parse_inp_with_options`] changed in the puppet, or found wrong with it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    /// Nodes whose name was normalized, with their name as stored in the file.
+    pub renamed_nodes: Vec<(NodeUuid, String)>,
+    /// Parameters whose name was normalized, with their name as stored in the file.
+    pub renamed_params: Vec<(ParamUuid, String)>,
+    /// Offsets in the JSON payload of the non-finite numbers that were read as zeroes,
+    /// see [`ParseOptions::lenient_numbers`].
+    pub replaced_numbers: Vec<usize>,
+    /// Parameters clashing with an earlier one, see [`Puppet::param_conflicts`], whatever the options.
+    ///
+    /// With [`ParseOptions::drop_duplicate_params`], the ones with a duplicate UUID were dropped.
+    pub param_conflicts: Vec<ParamConflict>,
+    /// Checksums of the textures in order, if [`ParseOptions::texture_checksums`] is set.
+    pub texture_checksums: Vec<u32>,
+}
+
+/// `name` without control characters and surrounding whitespace, or `None` if it already is.
+fn normalize_name(name: &str) -> Option<String> {
+    let normalized: String = name.chars().filter(|c| !c.is_control()).collect();
+    let normalized = normalized.trim();
+    (normalized != name).then(|| normalized.to_owned())
+}
+
+fn normalize_names(puppet: &mut Puppet, report: &mut ParseReport) {
+    for (uuid, node) in puppet.nodes.iter_mut() {
+        let name = &mut node.get_node_state_mut().name;
+        if let Some(normalized) = normalize_name(name) {
+            report
+                .renamed_nodes
+                .push((uuid, std::mem::replace(name, normalized)));
+        }
+    }
+    for param in &mut puppet.parameters {
+        if let Some(normalized) = normalize_name(&param.name) {
+            report
+                .renamed_params
+                .push((param.uuid, std::mem::replace(&mut param.name, normalized)));
+        }
+    }
+}
+
+/// Parses an `.inp` file from a reader like [`parse_inp_reader`], then processes it according to `options`.
+pub fn parse_inp_with_options<R: Read>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<(Model, ParseReport), InpParseError> {
+    let mut report = ParseReport::default();
+    let (puppet, textures) = parse_inp_json(
+        reader,
+        None,
+        options.max_depth,
+        options
+            .lenient_numbers
+            .then_some(&mut report.replaced_numbers),
+        check_version,
+        Vec::new(),
+        |texture| texture,
+    )?;
+    let mut model = Model { puppet, textures };
+    if options.normalize_names {
+        normalize_names(&mut model.puppet, &mut report);
+    }
+    report.param_conflicts = model.puppet.param_conflicts();
+    #[cfg(feature = "tracing")]
+    for conflict in &report.param_conflicts {
+        tracing::warn!(?conflict, "Conflicting parameter");
+    }
+    if options.drop_duplicate_params {
+        model.puppet.drop_duplicate_params();
+    }
+    if options.texture_checksums {
+        report.texture_checksums = model.textures.iter().map(ModelTexture::checksum).collect();
+    }
+    Ok((model, report))
+}
+
+/// Parses an `.inp` file from a reader like [`parse_inp_reader`],
+/// checking the lengths declared in the file against what's left of it before reading anything.
+///
+/// A corrupted or malicious length then fails with [`InpParseError::LengthExceedsInput`]
+/// instead of reading until the end of the input.
+/// Wrap slices in an [`io::Cursor`] to use this with them.
+pub fn parse_inp_seek<R: Read + Seek>(mut reader: R) -> Result<Model, InpParseError> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let remaining = Some(end.saturating_sub(start));
+    let (puppet, textures) = parse_inp_with(reader, remaining, Vec::new(), |texture| texture)?;
+    Ok(Model { puppet, textures })
+}
+
+/// Metadata of a puppet along with its thumbnail, see [`parse_inp_meta_only`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct InpMeta {
+    pub meta: PuppetMeta,
+    /// Texture [`PuppetMeta::thumbnail_id`] refers to, or `None` if the puppet has no thumbnail.
+    pub thumbnail: Option<ModelTexture>,
+}
+
+/// Part of the payload of an `.inp` file read by [`parse_inp_meta_only`], the rest is skipped.
+#[derive(serde::Deserialize)]
+struct MetaPayload {
+    meta: PuppetMeta,
+}
+
+/// Parses only the metadata and the thumbnail of an `.inp` file, e.g. to list puppets in a picker.
+///
+/// The rest of the payload isn't deserialized, and the textures before the thumbnail are read and discarded.
+/// Nothing is read past the thumbnail. See [`parse_inp_meta_only_seek`] to skip textures without reading them.
+pub fn parse_inp_meta_only<R: Read>(reader: R) -> Result<InpMeta, InpParseError> {
+    parse_meta_with(reader, None, |reader, len| {
+        io::copy(&mut reader.by_ref().take(len), &mut io::sink())
+    })
+}
+
+/// Parses only the metadata and the thumbnail of an `.inp` file like [`parse_inp_meta_only`],
+/// seeking past the textures before the thumbnail instead of reading them.
+///
+/// Lengths are checked against the size of the input like in [`parse_inp_seek`].
+pub fn parse_inp_meta_only_seek<R: Read + Seek>(mut reader: R) -> Result<InpMeta, InpParseError> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let remaining = Some(end.saturating_sub(start));
+    parse_meta_with(reader, remaining, Input::seek_forward)
+}
+
+/// Parses the metadata and the thumbnail of an `.inp` file, calling `skip` to go past the other textures.
+///
+/// `skip` returns how many bytes it skipped, which is less than asked if the input is truncated.
+fn parse_meta_with<R: Read>(
+    reader: R,
+    remaining: Option<u64>,
+    mut skip: impl FnMut(&mut Input<R>, u64) -> io::Result<u64>,
+) -> Result<InpMeta, InpParseError> {
+    let mut reader = Input { reader, remaining };
+    let MetaPayload { meta } = read_puppet(
+        &mut reader,
+        DEFAULT_MAX_DEPTH,
+        None,
+        |payload: &MetaPayload| check_meta_version(&payload.meta),
+    )?;
+    let Some(thumbnail_id) = meta.thumbnail_id else {
+        return Ok(InpMeta {
+            meta,
+            thumbnail: None,
+        });
+    };
+
+    let num_textures = read_texture_header(&mut reader)?;
+    if thumbnail_id >= num_textures {
+        return Ok(InpMeta {
+            meta,
+            thumbnail: None,
+        });
+    }
+    for index in 0..thumbnail_id {
+        let section = InpSection::Texture(index);
+        // The format byte comes before the data
+        let len = read_be_u32(&mut reader, section)? as u64 + 1;
+        reader.check_len(len, section)?;
+        let skipped = skip(&mut reader, len)?;
+        if skipped != len {
+            return Err(InpParseError::Truncated {
+                section,
+                expected: len,
+                got: skipped,
+            });
+        }
+    }
+    let thumbnail = read_texture(&mut reader, thumbnail_id)?;
+
+    Ok(InpMeta {
+        meta,
+        thumbnail: Some(thumbnail),
+    })
+}
+
+/// Allocations kept between calls to [`parse_inp_reuse`].
+#[derive(Debug, Default)]
+pub struct NodeTreeScratch {
+    arena: Arena<Box<dyn Node>>,
+    textures: Vec<ModelTexture>,
+}
+
+impl NodeTreeScratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes back a model that's no longer needed, so that the next parse can reuse its allocations.
+    pub fn recycle(&mut self, model: Model) {
+        let mut arena = model.puppet.nodes.arena;
+        arena.clear();
+        if arena.capacity() > self.arena.capacity() {
+            self.arena = arena;
+        }
+        let mut textures = model.textures;
+        textures.clear();
+        if textures.capacity() > self.textures.capacity() {
+            self.textures = textures;
+        }
+    }
+}
+
+/// Parses an `.inp` file from a reader like [`parse_inp_reader`],
+/// reusing the allocations of models given back to `scratch` with [`NodeTreeScratch::recycle`].
+///
+/// Meant for loading many puppets in a row, e.g. in a batch converter.
+pub fn parse_inp_reuse<R: Read>(
+    reader: R,
+    scratch: &mut NodeTreeScratch,
+) -> Result<Model, InpParseError> {
+    let textures = std::mem::take(&mut scratch.textures);
+    let (puppet, textures) = NodeTree::deserialize_in(&mut scratch.arena, || {
+        parse_inp_with(reader, None, textures, |texture| texture)
+    })?;
+    Ok(Model { puppet, textures })
+}
+
+/// Parses an `.inp` file from a reader like [`parse_inp_reader`],
+/// adding its textures to a store shared with other models.
+///
+/// Textures that are already in the store aren't loaded twice.
+pub fn parse_inp_into<R: Read>(
+    store: &mut TextureStore,
+    reader: R,
+) -> Result<SharedModel, InpParseError> {
+    let (puppet, textures) =
+        parse_inp_with(reader, None, Vec::new(), |texture| store.insert(texture))?;
+    Ok(SharedModel { puppet, textures })
+}
+
+/// Parses an `.inp` file, passing each texture to `add_texture` as it is read
+/// and pushing the results to `textures` once it has been cleared.
+///
+/// Declared lengths are checked against `remaining`, the size of the input if it is known.
+fn parse_inp_with<R: Read, T>(
+    reader: R,
+    remaining: Option<u64>,
+    textures: Vec<T>,
+    add_texture: impl FnMut(ModelTexture) -> T,
+) -> Result<(Puppet, Vec<T>), InpParseError> {
+    parse_inp_json(
+        reader,
+        remaining,
+        DEFAULT_MAX_DEPTH,
+        None,
+        check_version,
+        textures,
+        add_texture,
+    )
+}
+
+/// Parses an `.inp` file like [`parse_inp_with`], deserializing its payload as a `P`
+/// that has to pass `check` before the textures are read.
+///
+/// Fails if the payload nests more than `max_depth` levels deep.
+/// See [`read_puppet`] for `replaced_numbers`.
+fn parse_inp_json<R: Read, P: DeserializeOwned, T>(
+    reader: R,
+    remaining: Option<u64>,
+    max_depth: usize,
+    replaced_numbers: Option<&mut Vec<usize>>,
+    check: impl FnOnce(&P) -> Result<(), InpParseError>,
+    mut textures: Vec<T>,
+    mut add_texture: impl FnMut(ModelTexture) -> T,
+) -> Result<(P, Vec<T>), InpParseError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("parse_inp").entered();
+
+    let mut reader = Input { reader, remaining };
+    let puppet = read_puppet(&mut reader, max_depth, replaced_numbers, check)?;
+    let num_textures = read_texture_header(&mut reader)?;
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("textures", count = num_textures).entered();
+    textures.clear();
+    for i in 0..num_textures {
+        textures.push(add_texture(read_texture(&mut reader, i)?));
+    }
+
+    Ok((puppet, textures))
+}
+
+/// Reads the header and the payload of an `.inp` file, deserializing the payload as a `P` that has to pass `check`.
+///
+/// With `replaced_numbers`, non-finite numbers are replaced with zeroes and their offsets pushed to it,
+/// see [`ParseOptions::lenient_numbers`]. The payload is then read in memory before being deserialized.
+fn read_puppet<R: Read, P: DeserializeOwned>(
+    reader: &mut Input<R>,
+    max_depth: usize,
+    replaced_numbers: Option<&mut Vec<usize>>,
+    check: impl FnOnce(&P) -> Result<(), InpParseError>,
+) -> Result<P, InpParseError> {
+    if read_array::<_, 8>(reader, InpSection::Header)? != MAGIC {
+        return Err(InpParseError::InvalidMagic);
+    }
+
+    let json_len = read_be_u32(reader, InpSection::Header)?;
+    reader.check_len(json_len as u64, InpSection::Puppet)?;
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("puppet", len = json_len).entered();
+    let puppet = if let Some(replaced_numbers) = replaced_numbers {
+        let mut payload = read_vec(reader, json_len as usize, InpSection::Puppet)?;
+        let replaced = json::replace_non_finite(&mut payload);
+        #[cfg(feature = "tracing")]
+        if !replaced.is_empty() {
+            tracing::warn!("Replaced non-finite numbers with 0 at offsets {replaced:?}");
+        }
+        replaced_numbers.extend(replaced);
+        json::from_slice(&payload, max_depth)
+    } else {
+        let mut json = reader.by_ref().take(json_len as u64);
+        let puppet = json::from_reader(&mut json, max_depth);
+        // Make sure we end up right after the payload even if serde didn't read all of it
+        io::copy(&mut json, &mut io::sink())?;
+        if json.limit() != 0 {
+            return Err(InpParseError::Truncated {
+                section: InpSection::Puppet,
+                expected: json_len as u64,
+                got: json_len as u64 - json.limit(),
+            });
+        }
+        puppet
+    };
+    #[cfg(feature = "tracing")]
+    if let Err(error) = &puppet {
+        tracing::error!("Could not deserialize the puppet: {error}");
+    }
+    let puppet = puppet?;
+    check(&puppet)?;
+    Ok(puppet)
+}
+
+/// Reads the header of the texture section, returning the number of textures.
+fn read_texture_header<R: Read>(reader: &mut Input<R>) -> Result<u32, InpParseError> {
+    if read_array::<_, 8>(reader, InpSection::TextureHeader)? != TEX {
+        return Err(InpParseError::MissingTextureSection);
+    }
+
+    let num_textures = read_be_u32(reader, InpSection::TextureHeader)?;
+    // Each texture takes at least its length and format
+    reader.check_len(num_textures as u64 * 5, InpSection::TextureHeader)?;
+    Ok(num_textures)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InpWriteError {
+    #[error("Could not write the file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Could not serialize the puppet: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The {0} is too large for an .inp file")]
+    TooLarge(InpSection),
+    #[error("Textures can't be stored as {0:?} in an .inp file")]
+    UnsupportedTextureFormat(ImageFormat),
+}
+
+fn write_len<W: Write>(
+    writer: &mut W,
+    len: usize,
+    section: InpSection,
+) -> Result<(), InpWriteError> {
+    let len = u32::try_from(len).map_err(|_| InpWriteError::TooLarge(section))?;
+    writer.write_all(&len.to_be_bytes())?;
+    Ok(())
+}
+
+/// Writes a model as an `.inp` file, which [`parse_inp_reader`] reads back.
+pub fn write_inp<W: Write>(writer: W, model: &Model) -> Result<(), InpWriteError> {
+    let json = serde_json::to_vec(&model.puppet)?;
+    write_inp_with(writer, &json, &model.textures)
+}
+
+/// Writes a puppet read by [`parse_inp_raw`] as an `.inp` file, along with its textures.
+pub fn write_inp_raw<W: Write>(
+    writer: W,
+    puppet: &serde_json::Value,
+    textures: &[ModelTexture],
+) -> Result<(), InpWriteError> {
+    let json = serde_json::to_vec(puppet)?;
+    write_inp_with(writer, &json, textures)
+}
+
+fn write_inp_with<W: Write>(
+    mut writer: W,
+    json: &[u8],
+    textures: &[ModelTexture],
+) -> Result<(), InpWriteError> {
+    writer.write_all(MAGIC)?;
+    write_len(&mut writer, json.len(), InpSection::Puppet)?;
+    writer.write_all(json)?;
+
+    writer.write_all(TEX)?;
+    write_len(&mut writer, textures.len(), InpSection::TextureHeader)?;
+    for (i, texture) in textures.iter().enumerate() {
+        let format = match texture.format {
+            ImageFormat::Png => 0,
+            ImageFormat::Tga => 1,
+            format => return Err(InpWriteError::UnsupportedTextureFormat(format)),
+        };
+        write_len(
+            &mut writer,
+            texture.data.len(),
+            InpSection::Texture(i as u32),
+        )?;
+        writer.write_all(&[format])?;
+        writer.write_all(&texture.data)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::node::NodeUuid;
+
+    fn node(uuid: u32, name: &str, children: Vec<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({
+            "type": "Node",
+            "uuid": uuid,
+            "name": name,
+            "enabled": true,
+            "zsort": 0.,
+            "transform": { "trans": [0., 0., 0.], "rot": [0., 0., 0.], "scale": [1., 1.] },
+            "lockToRoot": false,
+            "children": children,
+        })
+    }
+
+    /// Payload of a puppet with a root and a single child.
+    fn puppet_json() -> Vec<u8> {
+        let puppet = serde_json::json!({
+            "meta": { "version": "1.0-alpha" },
+            "physics": { "pixelsPerMeter": 1000., "gravity": 9.8 },
+            "nodes": node(0, "Root", vec![node(1, "Child", Vec::new())]),
+            "param": [],
+        });
+        serde_json::to_vec(&puppet).unwrap()
+    }
+
+    /// An `.inp` file made of `json` followed by the textures, as `(format, data)`.
+    fn inp(json: &[u8], textures: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut inp = MAGIC.to_vec();
+        inp.extend((json.len() as u32).to_be_bytes());
+        inp.extend(json);
+        inp.extend(TEX);
+        inp.extend((textures.len() as u32).to_be_bytes());
+        for (format, data) in textures {
+            inp.extend((data.len() as u32).to_be_bytes());
+            inp.push(*format);
+            inp.extend(*data);
+        }
+        inp
+    }
+
+    #[test]
+    fn reader_stops_right_after_the_file() {
+        let mut file = inp(&puppet_json(), &[(0, b"png"), (1, b"tga")]);
+        let len = file.len();
+        file.extend(b"trailing");
+
+        let mut reader = file.as_slice();
+        let model = parse_inp_reader(&mut reader).unwrap();
+        assert_eq!(reader, b"trailing");
+
+        let (_, expected) = parse_inp(&file[..len]).unwrap();
+        let textures = |model: &Model| -> Vec<_> {
+            let textures = model.textures.iter();
+            textures
+                .map(|texture| (texture.format, texture.data.clone()))
+                .collect()
+        };
+        assert_eq!(textures(&model), textures(&expected));
+        let child = model.puppet.nodes.get_node(NodeUuid(1)).unwrap();
+        assert_eq!(child.get_node_state().name, "Child");
+    }
+
+    #[test]
+    fn truncated_sections_are_reported() {
+        let json = puppet_json();
+        let file = inp(&json, &[(0, b"texture data")]);
+        let json_len = json.len() as u64;
+        let textures = 12 + json.len() + 12;
+
+        let cases = [
+            (5, InpSection::Header, 8, 5),
+            (10, InpSection::Header, 4, 2),
+            (
+                12 + json.len() - 3,
+                InpSection::Puppet,
+                json_len,
+                json_len - 3,
+            ),
+            (12 + json.len() + 10, InpSection::TextureHeader, 4, 2),
+            (textures + 2, InpSection::Texture(0), 4, 2),
+            (textures + 4, InpSection::Texture(0), 1, 0),
+            (textures + 10, InpSection::Texture(0), 12, 5),
+        ];
+        for (len, section, expected, got) in cases {
+            match parse_inp_reader(&file[..len]) {
+                Err(InpParseError::Truncated {
+                    section: s,
+                    expected: e,
+                    got: g,
+                }) => assert_eq!((s, e, g), (section, expected, got), "cut at {len}"),
+                result => panic!("cut at {len}: expected a truncation, got {result:?}"),
+            }
+        }
+        assert!(parse_inp_reader(file.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn puppet_without_textures_loads() {
+        let file = inp(&puppet_json(), &[]);
+
+        let (rest, model) = parse_inp(&file).unwrap();
+        assert!(rest.is_empty());
+        assert!(!model.has_textures());
+
+        let mut model = parse_inp_reader(file.as_slice()).unwrap();
+        assert!(!model.has_textures());
+        assert!(model.puppet.nodes.get_node(NodeUuid(1)).is_some());
+        assert_eq!(model.validate(), Ok(()));
+        assert_eq!(model.dedup_textures(), 0);
+    }
+
+    #[test]
+    fn reuse_takes_the_recycled_allocations() {
+        let file = inp(&puppet_json(), &[(0, b"png"), (1, b"tga")]);
+        let mut scratch = NodeTreeScratch::new();
+
+        let model = parse_inp_reuse(file.as_slice(), &mut scratch).unwrap();
+        let textures = model.textures.as_ptr();
+        scratch.recycle(model);
+        assert!(scratch.arena.is_empty());
+        assert!(scratch.arena.capacity() >= 2);
+        assert!(scratch.textures.is_empty());
+
+        let model = parse_inp_reuse(file.as_slice(), &mut scratch).unwrap();
+        assert_eq!(model.textures.as_ptr(), textures);
+        assert_eq!(model.textures.len(), 2);
+        assert_eq!(model.textures[1].data, b"tga");
+        let child = model.puppet.nodes.get_node(NodeUuid(1)).unwrap();
+        assert_eq!(child.get_node_state().name, "Child");
+        assert_eq!(scratch.arena.capacity(), 0);
+    }
+
+    #[test]
+    fn seek_rejects_lengths_past_the_end_before_reading() {
+        let json = puppet_json();
+        let file = inp(&json, &[(0, b"texture data")]);
+        let textures = 12 + json.len() + 12;
+        assert!(parse_inp_seek(io::Cursor::new(&file)).is_ok());
+
+        let patch = |at: usize, value: u32| {
+            let mut file = file.clone();
+            file[at..at + 4].copy_from_slice(&value.to_be_bytes());
+            file
+        };
+        let cases = [
+            // The puppet payload, one byte past the end of the file
+            (patch(8, file.len() as u32 - 11), 12, InpSection::Puppet),
+            (patch(8, u32::MAX), 12, InpSection::Puppet),
+            // A texture's data, counting its format byte
+            (patch(textures, 13), textures + 4, InpSection::Texture(0)),
+            // The texture count, each taking at least 5 bytes
+            (patch(textures - 4, 4), textures, InpSection::TextureHeader),
+            (
+                patch(textures - 4, u32::MAX),
+                textures,
+                InpSection::TextureHeader,
+            ),
+        ];
+        for (file, position, section) in cases {
+            let declared = u32::from_be_bytes(file[position - 4..position].try_into().unwrap());
+            let declared = match section {
+                InpSection::Texture(_) => declared as u64 + 1,
+                InpSection::TextureHeader => declared as u64 * 5,
+                _ => declared as u64,
+            };
+            let remaining = (file.len() - position) as u64;
+
+            let mut cursor = io::Cursor::new(&file);
+            match parse_inp_seek(&mut cursor) {
+                Err(InpParseError::LengthExceedsInput {
+                    section: s,
+                    declared: d,
+                    remaining: r,
+                }) => assert_eq!((s, d, r), (section, declared, remaining)),
+                result => panic!("{section}: expected a length error, got {result:?}"),
+            }
+            // Nothing past the length was read
+            assert_eq!(cursor.position(), position as u64, "{section}");
+        }
+
+        // Three textures still fit in the 17 bytes left, the count check lets them through
+        let file = patch(textures - 4, 3);
+        assert!(matches!(
+            parse_inp_seek(io::Cursor::new(&file)),
+            Err(InpParseError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn texture_length_of_u32_max_fails() {
+        let mut file = inp(&puppet_json(), &[]);
+        // One texture instead of none, with a length that wraps to 0 once its format byte is counted
+        let num_textures = file.len() - 4;
+        file[num_textures..].copy_from_slice(&1u32.to_be_bytes());
+        file.extend(u32::MAX.to_be_bytes());
+        file.push(0);
+
+        match parse_inp(&file) {
+            Err(nom::Err::Failure(error)) => assert_eq!(error.code, ErrorKind::TooLarge),
+            result => panic!("expected a failure, got {result:?}"),
+        }
+        assert!(matches!(
+            parse_inp_reader(file.as_slice()),
+            Err(InpParseError::Truncated { .. })
+        ));
+        assert!(matches!(
+            parse_inp_seek(io::Cursor::new(&file)),
+            Err(InpParseError::LengthExceedsInput { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_texture_formats_fail() {
+        for format in [2, 7] {
+            let file = inp(&puppet_json(), &[(format, &[0; 4])]);
+
+            match parse_inp(&file) {
+                Err(nom::Err::Failure(error)) => assert_eq!(error.code, ErrorKind::Verify),
+                result => panic!("expected a failure, got {result:?}"),
+            }
+            assert!(matches!(
+                parse_inp_reader(file.as_slice()),
+                Err(InpParseError::UnsupportedTextureFormat(found)) if found == format
+            ));
+        }
+    }
+
+    #[test]
+    fn normalize_names_reports_what_it_renamed() {
+        let mut puppet: serde_json::Value = serde_json::from_slice(&puppet_json()).unwrap();
+        puppet["nodes"]["children"][0]["name"] = " Left\tarm\u{7}\n".into();
+        puppet["param"] = serde_json::json!([
+            {
+                "uuid": 7,
+                "name": "Head X",
+                "is_vec2": false,
+                "min": [0., 0.],
+                "max": [1., 1.],
+                "defaults": [0., 0.],
+                "axis_points": [[0., 1.], [0.]],
+                "bindings": [],
+            },
+            {
+                "uuid": 8,
+                "name": "\u{1b}Head Y ",
+                "is_vec2": false,
+                "min": [0., 0.],
+                "max": [1., 1.],
+                "defaults": [0., 0.],
+                "axis_points": [[0., 1.], [0.]],
+                "bindings": [],
+            },
+        ]);
+        let file = inp(&serde_json::to_vec(&puppet).unwrap(), &[]);
+
+        let options = ParseOptions::default();
+        let (model, report) = parse_inp_with_options(file.as_slice(), &options).unwrap();
+        assert_eq!(report, ParseReport::default());
+        let child = model.puppet.nodes.get_node(NodeUuid(1)).unwrap();
+        assert_eq!(child.get_node_state().name, " Left\tarm\u{7}\n");
+
+        let options = ParseOptions {
+            normalize_names: true,
+            ..ParseOptions::default()
+        };
+        let (model, report) = parse_inp_with_options(file.as_slice(), &options).unwrap();
+        assert_eq!(
+            report.renamed_nodes,
+            [(NodeUuid(1), " Left\tarm\u{7}\n".to_owned())]
+        );
+        assert_eq!(
+            report.renamed_params,
+            [(ParamUuid(8), "\u{1b}Head Y ".to_owned())]
+        );
+        let child = model.puppet.nodes.get_node(NodeUuid(1)).unwrap();
+        assert_eq!(child.get_node_state().name, "Leftarm");
+        let names: Vec<_> = model
+            .puppet
+            .parameters
+            .iter()
+            .map(|param| &param.name)
+            .collect();
+        assert_eq!(names, ["Head X", "Head Y"]);
+    }
+
+    #[test]
+    fn max_depth_counts_every_array_and_object() {
+        // The translations of the child node are 6 levels deep
+        let file = inp(&puppet_json(), &[]);
+        let options = |max_depth| ParseOptions {
+            max_depth,
+            ..ParseOptions::default()
+        };
+
+        assert!(parse_inp_with_options(file.as_slice(), &options(6)).is_ok());
+        match parse_inp_with_options(file.as_slice(), &options(5)) {
+            Err(InpParseError::TooDeep { max_depth }) => assert_eq!(max_depth, 5),
+            result => panic!("expected the puppet to be too deep, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn meta_only_skips_the_puppet_and_other_textures() {
+        // Nodes that don't deserialize, to check that only the metadata is read
+        let mut puppet: serde_json::Value = serde_json::from_slice(&puppet_json()).unwrap();
+        puppet["nodes"] = 42.into();
+        puppet["meta"]["name"] = "Puppet".into();
+        puppet["meta"]["thumbnailId"] = 1.into();
+        let json = serde_json::to_vec(&puppet).unwrap();
+        let file = inp(&json, &[(0, b"albedo"), (1, b"thumbnail"), (0, b"after")]);
+        assert!(parse_inp_reader(file.as_slice()).is_err());
+        // The header, the payload, the texture header then the first two textures
+        let thumbnail_end = 12 + json.len() + 12 + (5 + 6) + (5 + 9);
+
+        let mut reader = file.as_slice();
+        let meta = parse_inp_meta_only(&mut reader).unwrap();
+        assert_eq!(meta.meta.name.as_deref(), Some("Puppet"));
+        let thumbnail = meta.thumbnail.unwrap();
+        assert_eq!(thumbnail.format, ImageFormat::Tga);
+        assert_eq!(thumbnail.data, b"thumbnail");
+        assert_eq!(reader, &file[thumbnail_end..]);
+
+        let mut cursor = io::Cursor::new(&file);
+        let seeked = parse_inp_meta_only_seek(&mut cursor).unwrap();
+        assert_eq!(seeked.thumbnail.unwrap().data, b"thumbnail");
+        assert_eq!(cursor.position(), thumbnail_end as u64);
+
+        // Without a thumbnail, the texture section isn't touched
+        puppet["meta"]["thumbnailId"] = serde_json::Value::Null;
+        let json = serde_json::to_vec(&puppet).unwrap();
+        let file = inp(&json, &[(0, b"albedo")]);
+        let mut reader = file.as_slice();
+        assert_eq!(parse_inp_meta_only(&mut reader).unwrap().thumbnail, None);
+        assert_eq!(reader.len(), 12 + 5 + 6);
+    }
+
+    #[test]
+    fn round_trip_keeps_render_order() {
+        let to_inp = |model: &Model| {
+            let mut inp = Vec::new();
+            write_inp(&mut inp, model).unwrap();
+            inp
+        };
+        let fixture: &[u8] = include_bytes!("../../benches/fixtures/puppet.inp");
+        let model = parse_inp_reader(fixture).unwrap();
+        let zsorted = model.puppet.nodes.zsorted();
+
+        let model = parse_inp_reader(to_inp(&model).as_slice()).unwrap();
+        assert_eq!(model.puppet.nodes.zsorted(), zsorted);
+        assert_eq!(to_inp(&model), to_inp(&parse_inp_reader(fixture).unwrap()));
+    }
+
+    #[test]
+    fn texture_checksums_find_corrupt_textures() {
+        let file = inp(&puppet_json(), &[(0, b"123456789"), (1, b"tga")]);
+        let (_, report) =
+            parse_inp_with_options(file.as_slice(), &ParseOptions::default()).unwrap();
+        assert!(report.texture_checksums.is_empty());
+
+        let options = ParseOptions {
+            texture_checksums: true,
+            ..ParseOptions::default()
+        };
+        let (mut model, report) = parse_inp_with_options(file.as_slice(), &options).unwrap();
+        let checksums = report.texture_checksums;
+        // The check value of CRC-32
+        assert_eq!(checksums[0], 0xcbf4_3926);
+        assert_eq!(model.verify_textures(&checksums), Vec::<usize>::new());
+
+        model.textures[1].data[0] ^= 1;
+        assert_eq!(model.verify_textures(&checksums), [1]);
+        assert_eq!(model.verify_textures(&checksums[..1]), [1]);
+        assert_eq!(model.verify_textures(&[checksums[0], 0, 0]), [1, 2]);
+    }
+}
@@ -0,0 +1,230 @@
+//! Deserializing JSON with a limit on how deeply it nests.
+//!
+//! serde_json stops at 128 levels on its own, which is only about 60 levels of nodes.
+//! Its limit is turned off here for one that is configurable,
+//! enforced by scanning the bytes before serde sees them, without recursing.
+
+use std::io::{self, BufReader, Read};
+
+use serde::de::DeserializeOwned;
+
+/// Tracks how deeply the arrays and objects of a JSON document nest as its bytes go by.
+#[derive(Debug)]
+struct DepthScanner {
+    max_depth: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl DepthScanner {
+    fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    /// Returns `false` as soon as the limit is exceeded.
+    fn scan(&mut self, bytes: &[u8]) -> bool {
+        for &byte in bytes {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => {
+                    self.depth += 1;
+                    if self.depth > self.max_depth {
+                        return false;
+                    }
+                }
+                b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+/// Error of [`from_reader`] and [`from_slice`].
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum JsonError {
+    #[error("Nests more than {max_depth} levels deep")]
+    TooDeep { max_depth: usize },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Reader failing once the JSON going through it nests too deeply.
+struct DepthLimited<R> {
+    reader: R,
+    scanner: DepthScanner,
+    too_deep: bool,
+}
+
+impl<R: Read> Read for DepthLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if !self.scanner.scan(&buf[..read]) {
+            self.too_deep = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "JSON nests too deeply",
+            ));
+        }
+        Ok(read)
+    }
+}
+
+fn deserialize<'de, P: DeserializeOwned, R: serde_json::de::Read<'de>>(
+    mut deserializer: serde_json::Deserializer<R>,
+) -> serde_json::Result<P> {
+    deserializer.disable_recursion_limit();
+    let value = P::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Deserializes JSON from a reader, failing if arrays and objects nest more than `max_depth` levels.
+pub(crate) fn from_reader<P: DeserializeOwned>(
+    reader: impl Read,
+    max_depth: usize,
+) -> Result<P, JsonError> {
+    let mut reader = DepthLimited {
+        reader,
+        scanner: DepthScanner::new(max_depth),
+        too_deep: false,
+    };
+    let result = deserialize(serde_json::Deserializer::from_reader(BufReader::new(
+        &mut reader,
+    )));
+    if reader.too_deep {
+        return Err(JsonError::TooDeep { max_depth });
+    }
+    Ok(result?)
+}
+
+/// Deserializes JSON from a slice, failing if arrays and objects nest more than `max_depth` levels.
+pub(crate) fn from_slice<P: DeserializeOwned>(
+    json: &[u8],
+    max_depth: usize,
+) -> Result<P, JsonError> {
+    if !DepthScanner::new(max_depth).scan(json) {
+        return Err(JsonError::TooDeep { max_depth });
+    }
+    Ok(deserialize(serde_json::Deserializer::from_slice(json))?)
+}
+
+/// Replaces the `NaN`, `Infinity` and `-Infinity` that some tools write as if they were numbers with zeroes,
+/// padded with whitespace so that the other values stay in place. Strings are left untouched.
+///
+/// Returns the offsets of the replaced values.
+pub(crate) fn replace_non_finite(json: &mut [u8]) -> Vec<usize> {
+    const TOKENS: [(&[u8], &[u8]); 2] = [(b"NaN", b"0.0"), (b"Infinity", b"0.0     ")];
+
+    let mut replaced = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < json.len() {
+        let byte = json[i];
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if let Some((token, zero)) = TOKENS
+            .into_iter()
+            .find(|(token, _)| json[i..].starts_with(token))
+        {
+            // `-Infinity` becomes `-0.0`, which is still a valid number
+            let start = if i > 0 && json[i - 1] == b'-' {
+                i - 1
+            } else {
+                i
+            };
+            json[i..i + token.len()].copy_from_slice(zero);
+            replaced.push(start);
+            i += token.len();
+            continue;
+        }
+        i += 1;
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth_check(json: &str, max_depth: usize) -> Result<serde_json::Value, JsonError> {
+        let from_slice = from_slice(json.as_bytes(), max_depth);
+        let from_reader = from_reader(json.as_bytes(), max_depth);
+        assert_eq!(
+            from_slice.as_ref().ok(),
+            from_reader.as_ref().ok(),
+            "{json}"
+        );
+        from_slice
+    }
+
+    #[test]
+    fn too_deep_past_max_depth() {
+        let json = r#"{"a": [1, {"b": []}], "c": {}}"#;
+        // The empty array is 4 levels deep
+        assert!(depth_check(json, 5).is_ok());
+        assert!(depth_check(json, 4).is_ok());
+        assert!(matches!(
+            depth_check(json, 3),
+            Err(JsonError::TooDeep { max_depth: 3 })
+        ));
+        assert!(matches!(
+            from_reader::<serde_json::Value>(json.as_bytes(), 3),
+            Err(JsonError::TooDeep { max_depth: 3 })
+        ));
+    }
+
+    #[test]
+    fn brackets_in_strings_dont_nest() {
+        let json = r#"["[[[{{{", "\"[[[", "\\", "]]]}}}", {"[{": "}]"}]"#;
+        assert_eq!(depth_check(json, 2).unwrap()[1], "\"[[[");
+        assert!(matches!(
+            depth_check(json, 1),
+            Err(JsonError::TooDeep { max_depth: 1 })
+        ));
+    }
+
+    #[test]
+    fn non_finite_numbers_are_replaced_outside_strings() {
+        let json = r#"{"a": NaN, "b": -Infinity, "c": [Infinity, 1], "NaN": "Infinity \"NaN\" -Infinity", "d": "\\", "e": NaN}"#;
+        let mut bytes = json.as_bytes().to_vec();
+
+        let replaced = replace_non_finite(&mut bytes);
+        assert_eq!(replaced, [6, 16, 33, 100]);
+        assert_eq!(bytes.len(), json.len());
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            r#"{"a": 0.0, "b": -0.0     , "c": [0.0     , 1], "NaN": "Infinity \"NaN\" -Infinity", "d": "\\", "e": 0.0}"#
+        );
+
+        let value: serde_json::Value = from_slice(&bytes, 2).unwrap();
+        assert_eq!(value["b"], -0.);
+        assert_eq!(value["NaN"], r#"Infinity "NaN" -Infinity"#);
+        assert_eq!(value["d"], "\\");
+        assert_eq!(value["e"], 0.);
+    }
+}
@@ -1 +1,2 @@
 pub mod inp;
+mod json;
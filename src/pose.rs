@@ -0,0 +1,154 @@
+//! Compact binary encoding of [`PoseSnapshot`]s, e.g. to stream poses to a remote renderer.
+//!
+//! All numbers are little-endian. An encoded pose is laid out as:
+//!
+//! - the format version, [`POSE_FORMAT_VERSION`], as a byte,
+//! - the puppet ID given to [`encode_pose`] as a `u64`, see [`Puppet::pose_id`](crate::puppet::Puppet::pose_id),
+//! - the number of parameters as a `u32`,
+//! - for each parameter, its UUID as a `u32` followed by the X and Y of its value as `f32`s.
+
+use glam::Vec2;
+
+use crate::params::{ParamUuid, PoseSnapshot};
+
+/// Version of the format written by [`encode_pose`].
+pub const POSE_FORMAT_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 1 + 8 + 4;
+const ENTRY_LEN: usize = 4 + 4 + 4;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum PoseDecodeError {
+    #[error("Unsupported pose format version {0}, only {POSE_FORMAT_VERSION} is supported")]
+    UnsupportedVersion(u8),
+    #[error("The pose is truncated: expected {expected} bytes, got {got}")]
+    Truncated { expected: usize, got: usize },
+    #[error("The pose has {0} trailing bytes")]
+    TrailingBytes(usize),
+}
+
+/// Encodes a snapshot of the parameters of the puppet with ID `puppet_id`.
+pub fn encode_pose(puppet_id: u64, snapshot: &PoseSnapshot) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + snapshot.values.len() * ENTRY_LEN);
+    bytes.push(POSE_FORMAT_VERSION);
+    bytes.extend(puppet_id.to_le_bytes());
+    // Puppets don't have anywhere near u32::MAX parameters
+    bytes.extend((snapshot.values.len() as u32).to_le_bytes());
+    for (uuid, value) in &snapshot.values {
+        bytes.extend(uuid.as_u32().to_le_bytes());
+        bytes.extend(value.x.to_le_bytes());
+        bytes.extend(value.y.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decodes a pose encoded by [`encode_pose`], returning the ID of its puppet along with it.
+///
+/// Check the ID against [`Puppet::pose_id`](crate::puppet::Puppet::pose_id) before applying the pose.
+pub fn decode_pose(bytes: &[u8]) -> Result<(u64, PoseSnapshot), PoseDecodeError> {
+    let truncated = |expected| PoseDecodeError::Truncated {
+        expected,
+        got: bytes.len(),
+    };
+
+    let (&version, rest) = bytes.split_first().ok_or(truncated(HEADER_LEN))?;
+    if version != POSE_FORMAT_VERSION {
+        return Err(PoseDecodeError::UnsupportedVersion(version));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(truncated(HEADER_LEN));
+    }
+    let (puppet_id, rest) = rest.split_at(8);
+    let (count, entries) = rest.split_at(4);
+    let puppet_id = u64::from_le_bytes(puppet_id.try_into().unwrap());
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+
+    let expected = count
+        .checked_mul(ENTRY_LEN)
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .unwrap_or(usize::MAX);
+    if bytes.len() < expected {
+        return Err(truncated(expected));
+    }
+    if bytes.len() > expected {
+        return Err(PoseDecodeError::TrailingBytes(bytes.len() - expected));
+    }
+
+    let word = |entry: &[u8], i: usize| -> [u8; 4] { entry[i * 4..i * 4 + 4].try_into().unwrap() };
+    let values = entries
+        .chunks_exact(ENTRY_LEN)
+        .map(|entry| {
+            let uuid = ParamUuid::from_raw(u32::from_le_bytes(word(entry, 0)));
+            let x = f32::from_le_bytes(word(entry, 1));
+            let y = f32::from_le_bytes(word(entry, 2));
+            (uuid, Vec2::new(x, y))
+        })
+        .collect();
+    Ok((puppet_id, PoseSnapshot { values }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> PoseSnapshot {
+        let values = [(3, Vec2::new(0.5, -1.)), (7, Vec2::new(f32::MAX, 0.))];
+        PoseSnapshot {
+            values: values
+                .into_iter()
+                .map(|(uuid, value)| (ParamUuid::from_raw(uuid), value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = encode_pose(0x0123_4567_89ab_cdef, &snapshot());
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * ENTRY_LEN);
+        assert_eq!(bytes[0], POSE_FORMAT_VERSION);
+        assert_eq!(decode_pose(&bytes), Ok((0x0123_4567_89ab_cdef, snapshot())));
+
+        let empty = encode_pose(1, &PoseSnapshot::default());
+        assert_eq!(decode_pose(&empty), Ok((1, PoseSnapshot::default())));
+    }
+
+    #[test]
+    fn malformed_poses_are_rejected() {
+        let bytes = encode_pose(1, &snapshot());
+
+        let mut newer = bytes.clone();
+        newer[0] = POSE_FORMAT_VERSION + 1;
+        assert_eq!(
+            decode_pose(&newer),
+            Err(PoseDecodeError::UnsupportedVersion(POSE_FORMAT_VERSION + 1))
+        );
+
+        let truncated = |expected, got| Err(PoseDecodeError::Truncated { expected, got });
+        assert_eq!(decode_pose(&[]), truncated(HEADER_LEN, 0));
+        assert_eq!(decode_pose(&bytes[..5]), truncated(HEADER_LEN, 5));
+        assert_eq!(
+            decode_pose(&bytes[..bytes.len() - 1]),
+            truncated(bytes.len(), bytes.len() - 1)
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.extend([0; 3]);
+        assert_eq!(
+            decode_pose(&trailing),
+            Err(PoseDecodeError::TrailingBytes(3))
+        );
+    }
+
+    #[test]
+    fn huge_count_is_truncated_instead_of_overflowing() {
+        let mut bytes = encode_pose(1, &snapshot());
+        bytes[9..13].copy_from_slice(&u32::MAX.to_le_bytes());
+        match decode_pose(&bytes) {
+            Err(PoseDecodeError::Truncated { expected, got }) => {
+                assert_eq!(got, bytes.len());
+                assert!(expected > got);
+            }
+            result => panic!("expected a truncated pose, got {result:?}"),
+        }
+    }
+}
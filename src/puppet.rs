@@ -1,13 +1,28 @@
 #![allow(dead_code)]
 
-use glam::Vec2;
+use std::collections::{BTreeMap, BTreeSet};
+
+use glam::{Mat4, Vec2, Vec3};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::nodes::node::NodeUuid;
+use crate::animation::Animation;
+use crate::automation::Driver;
+use crate::hash::Fnv1a;
+use crate::math::rect::Rect;
+use crate::math::transform::Transform;
+use crate::nodes::composite::Composite;
+use crate::nodes::drawable::BlendMode;
+use crate::nodes::drivers::simple_physics::{is_valid_dt, PendulumState, SimplePhysics};
+use crate::nodes::drivers::{Cycle, DriverGraph};
+use crate::nodes::node::{NodeKind, NodeUuid};
 use crate::nodes::node_tree::NodeTree;
+use crate::nodes::part::Part;
+use crate::params::{Binding, MergeMode, Param, ParamUuid, PoseSnapshot};
 
 /// Who is allowed to use the puppet?
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PuppetAllowedUsers {
     /// Only the author(s) are allowed to use the puppet.
     #[default]
@@ -19,7 +34,8 @@ pub enum PuppetAllowedUsers {
 }
 
 /// Can the puppet be redistributed?
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PuppetAllowedRedistribution {
     /// Redistribution is prohibited
     #[default]
@@ -35,7 +51,8 @@ pub enum PuppetAllowedRedistribution {
 }
 
 /// Can the puppet be modified?
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PuppetAllowedModification {
     /// Modification is prohibited
     #[default]
@@ -48,7 +65,8 @@ pub enum PuppetAllowedModification {
 }
 
 /// Terms of usage of the puppet.
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PuppetUsageRights {
     /// Who is allowed to use the puppet?
     pub allowed_users: PuppetAllowedUsers,
@@ -66,9 +84,94 @@ pub struct PuppetUsageRights {
     pub require_attribution: bool,
 }
 
+/// Faces a renderer should cull.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+/// How the creator of the puppet intended it to be rendered.
+///
+/// Only hints: renderers are free to ignore them, and `None` leaves the choice to them.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RenderHints {
+    /// Whether the puppet should be rendered with antialiasing, e.g. MSAA.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub antialiasing: Option<bool>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub cull: Option<CullMode>,
+}
+
+/// Framing of a puppet, see [`Puppet::camera`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Camera {
+    /// Point of puppet space shown at the center of the viewport.
+    pub position: Vec2,
+    /// How many pixels of the viewport a unit of puppet space takes.
+    pub zoom: f32,
+}
+
+impl Camera {
+    /// Matrix mapping puppet space to normalized device coordinates for a `(width, height)` viewport,
+    /// framed by the camera. See [`Puppet::to_ndc_matrix`] for the unframed one.
+    pub fn matrix(&self, viewport: (f32, f32)) -> Mat4 {
+        Puppet::to_ndc_matrix(viewport)
+            * Mat4::from_scale(Vec3::new(self.zoom, self.zoom, 1.))
+            * Mat4::from_translation(-self.position.extend(0.))
+    }
+}
+
+/// Version of the Inochi2D spec, such as `1.0-alpha`.
+///
+/// Pre-release tags (the part after `-`) are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpecVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl SpecVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parses a version like `1.0-alpha` or `v1.0`, the minor version being optional.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let numbers = version.split('-').next()?;
+        let mut numbers = numbers.split('.');
+        let major = numbers.next()?.parse().ok()?;
+        let minor = match numbers.next() {
+            Some(minor) => minor.parse().ok()?,
+            None => 0,
+        };
+        Some(Self { major, minor })
+    }
+}
+
+impl std::fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
 /// Puppet meta information.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct PuppetMeta {
     /// Name of the puppet.
     pub name: Option<String>,
@@ -83,7 +186,7 @@ pub struct PuppetMeta {
     /// Copyright string.
     pub copyright: Option<String>,
     /// URL of the license.
-    #[serde(rename = "licenseURL")]
+    #[cfg_attr(feature = "serde", serde(rename = "licenseURL"))]
     pub license_url: Option<String>,
     /// Contact information of the first author.
     pub contact: Option<String>,
@@ -93,7 +196,32 @@ pub struct PuppetMeta {
     pub thumbnail_id: Option<u32>,
     /// Whether the puppet should preserve pixel borders.
     /// This feature is mainly useful for puppets that use pixel art.
+    ///
+    /// Renderers should sample textures with nearest-neighbor filtering when this is set.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub preserve_pixels: bool,
+    /// Version of Inochi Creator the puppet was last saved with, if it recorded it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub creator_version: Option<String>,
+    /// Hints on how to render the puppet, stored among the other metadata.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub render_hints: RenderHints,
+    /// How the creator of the puppet framed it, if they did.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub default_camera: Option<Camera>,
+}
+
+impl PuppetMeta {
+    /// Parsed `version`, or `None` if it isn't a valid version.
+    pub fn spec_version(&self) -> Option<SpecVersion> {
+        SpecVersion::parse(&self.version)
+    }
 }
 
 impl Default for PuppetMeta {
@@ -110,106 +238,1033 @@ impl Default for PuppetMeta {
             reference: Default::default(),
             thumbnail_id: Default::default(),
             preserve_pixels: Default::default(),
+            creator_version: Default::default(),
+            render_hints: Default::default(),
+            default_camera: Default::default(),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+/// Environment of the physics simulation, shared by all the puppet's physics nodes.
+///
+/// Physics nodes are simulated in pixels, and scale their own gravity by these settings.
 pub struct PuppetPhysics {
-    pixels_per_meter: f32,
-    gravity: f32,
+    /// How many pixels of puppet space make up a meter.
+    pub pixels_per_meter: f32,
+    /// Gravity in meters per second squared.
+    pub gravity: f32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum InterpolateMode {
-    Linear,
+impl Default for PuppetPhysics {
+    fn default() -> Self {
+        Self {
+            pixels_per_meter: 1000.,
+            gravity: 9.8,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BindingBase {
-    node: NodeUuid,
-    #[serde(rename = "isSet")]
-    is_set: Vec<Vec<bool>>,
-    interpolate_mode: InterpolateMode,
+/// Outcome of [`Puppet::flatten_composites`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlattenReport {
+    /// Composites that were dissolved into their parent.
+    pub flattened: Vec<NodeUuid>,
+    /// Composites that still need an offscreen pass to render correctly.
+    pub kept: Vec<NodeUuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "param_name")]
-pub enum Binding {
-    #[serde(rename = "zSort")]
-    ZSort {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
+/// Parameter clashing with an earlier one of the same puppet, see [`Puppet::param_conflicts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamConflict {
+    /// The parameter at `index` in [`Puppet::parameters`] has the UUID of an earlier one.
+    DuplicateUuid { index: usize, uuid: ParamUuid },
+    /// The parameter at `index` in [`Puppet::parameters`] has the name of the earlier one with UUID `first`.
+    DuplicateName {
+        index: usize,
+        uuid: ParamUuid,
+        first: ParamUuid,
+        name: String,
     },
-    #[serde(rename = "transform.t.x")]
-    TransformTX {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.t.y")]
-    TransformTY {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.s.x")]
-    TransformSX {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.s.y")]
-    TransformSY {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.r.x")]
-    TransformRX {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.r.y")]
-    TransformRY {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "transform.r.z")]
-    TransformRZ {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<f32>>,
-    },
-    #[serde(rename = "deform")]
-    Deform {
-        #[serde(flatten)]
-        base: BindingBase,
-        values: Vec<Vec<Vec<Vec2>>>,
-    },
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Param {
-    uuid: u32,
-    name: String,
-    is_vec2: bool,
-    min: Vec2,
-    max: Vec2,
-    defaults: Vec2,
-    axis_points: [Vec<f32>; 2],
-    bindings: Vec<Binding>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Puppet {
     pub meta: PuppetMeta,
     pub physics: PuppetPhysics,
     pub nodes: NodeTree,
-    #[serde(rename = "param")]
+    #[cfg_attr(feature = "serde", serde(rename = "param"))]
     pub parameters: Vec<Param>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub automation: Vec<Driver>,
+    /// Animations by name.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            deserialize_with = "crate::animation::deserialize_animations",
+            skip_serializing_if = "BTreeMap::is_empty"
+        )
+    )]
+    pub animations: BTreeMap<String, Animation>,
+    /// Nodes whose offsets changed since the last call to `clear_dirty`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dirty: BTreeSet<NodeUuid>,
+    /// Area covered by the dirty nodes before they changed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dirty_bounds: Option<Rect>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    track_dirty_bounds: bool,
+    /// Simulation state of each physics node, created on their first update.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    physics_state: BTreeMap<NodeUuid, PendulumState>,
+}
+
+/// Compares everything but the dirty nodes, which only track changes, and the physics state.
+impl PartialEq for Puppet {
+    fn eq(&self, other: &Self) -> bool {
+        self.meta == other.meta
+            && self.physics == other.physics
+            && self.nodes == other.nodes
+            && self.parameters == other.parameters
+            && self.automation == other.automation
+            && self.animations == other.animations
+    }
+}
+
+impl Puppet {
+    /// Makes a puppet with no automation nor animations.
+    pub fn new(
+        meta: PuppetMeta,
+        physics: PuppetPhysics,
+        nodes: NodeTree,
+        parameters: Vec<Param>,
+    ) -> Self {
+        Self {
+            meta,
+            physics,
+            nodes,
+            parameters,
+            automation: Vec::new(),
+            animations: BTreeMap::new(),
+            dirty: BTreeSet::new(),
+            dirty_bounds: None,
+            track_dirty_bounds: false,
+            physics_state: BTreeMap::new(),
+        }
+    }
+
+    /// How to frame the puppet in a `(width, height)` viewport on first load.
+    ///
+    /// This is the [`PuppetMeta::default_camera`] if the puppet has one, otherwise the puppet is centered
+    /// and fit to the viewport according to its current bounds.
+    /// Returns `None` if it has neither, i.e. no camera and no parts.
+    pub fn camera(&self, viewport: (f32, f32)) -> Option<Camera> {
+        if let Some(camera) = self.meta.default_camera {
+            return Some(camera);
+        }
+
+        let root = self.nodes.arena[self.nodes.root]
+            .get()
+            .get_node_state()
+            .uuid;
+        let bounds = self.nodes.bounds(root)?;
+        let (width, height) = viewport;
+        let size = bounds.size();
+        let zoom = (width / size.x).min(height / size.y);
+        Some(Camera {
+            position: (bounds.min + bounds.max) / 2.,
+            zoom: if zoom.is_finite() && zoom > 0. {
+                zoom
+            } else {
+                1.
+            },
+        })
+    }
+
+    /// Matrix mapping puppet space to normalized device coordinates for a `(width, height)` viewport.
+    ///
+    /// Puppet space is centered on the origin with one unit per pixel, and its Y axis points down
+    /// like in Inochi2D. NDC range from -1 to 1 with the Y axis pointing up,
+    /// so Y is flipped: the origin ends up at the center of the viewport,
+    /// and a point with a positive Y ends up in its bottom half.
+    ///
+    /// This maps raw puppet space only. The puppet's [scale](Puppet::set_scale) is already part
+    /// of [`NodeTree::world_transform`] and of the baked vertices, so it isn't applied again,
+    /// and there is no framing: use [`Camera::matrix`] to pan and zoom.
+    ///
+    /// ```
+    /// # use glam::{vec3, Vec3};
+    /// # use inox2d::puppet::Puppet;
+    /// let ndc = Puppet::to_ndc_matrix((800., 600.));
+    /// assert_eq!(ndc.transform_point3(Vec3::ZERO), Vec3::ZERO);
+    /// assert_eq!(ndc.transform_point3(vec3(400., 300., 0.)), vec3(1., -1., 0.));
+    /// ```
+    pub fn to_ndc_matrix(viewport: (f32, f32)) -> Mat4 {
+        let (width, height) = viewport;
+        Mat4::from_scale(Vec3::new(2. / width, -2. / height, 1.))
+    }
+
+    /// Parameters clashing with an earlier one, in order.
+    ///
+    /// The first parameter with a given UUID or name wins: it's the one lookups like [`Puppet::get_param`]
+    /// and [`Puppet::set_param`] find, and the ones after it are reported here.
+    /// A parameter with a duplicate UUID is only reported for that, whatever its name.
+    /// See [`Puppet::drop_duplicate_params`] to remove the duplicate UUIDs.
+    pub fn param_conflicts(&self) -> Vec<ParamConflict> {
+        let mut uuids = BTreeSet::new();
+        let mut names = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        for (index, param) in self.parameters.iter().enumerate() {
+            if !uuids.insert(param.uuid) {
+                conflicts.push(ParamConflict::DuplicateUuid {
+                    index,
+                    uuid: param.uuid,
+                });
+            } else if let Some(&first) = names.get(param.name.as_str()) {
+                conflicts.push(ParamConflict::DuplicateName {
+                    index,
+                    uuid: param.uuid,
+                    first,
+                    name: param.name.clone(),
+                });
+            } else {
+                names.insert(param.name.as_str(), param.uuid);
+            }
+        }
+        conflicts
+    }
+
+    /// Removes the parameters whose UUID is already used by an earlier one, returning them in order.
+    ///
+    /// They could never be set anyway, see [`Puppet::param_conflicts`].
+    pub fn drop_duplicate_params(&mut self) -> Vec<Param> {
+        let mut uuids = BTreeSet::new();
+        let (kept, dropped) = std::mem::take(&mut self.parameters)
+            .into_iter()
+            .partition(|param| uuids.insert(param.uuid));
+        self.parameters = kept;
+        dropped
+    }
+
+    /// Current value of the parameter with that name.
+    ///
+    /// If several parameters share the name, the first one is used.
+    pub fn get_param(&self, name: &str) -> Option<Vec2> {
+        self.parameters
+            .iter()
+            .find(|param| param.name == name)
+            .map(Param::value)
+    }
+
+    /// Current value of the parameter with that UUID.
+    pub fn get_param_by_uuid(&self, uuid: ParamUuid) -> Option<Vec2> {
+        self.parameters
+            .iter()
+            .find(|param| param.uuid == uuid)
+            .map(Param::value)
+    }
+
+    /// Sets the value of a parameter and applies its bindings.
+    ///
+    /// Returns `false` if the puppet has no parameter with that UUID.
+    pub fn set_param(&mut self, uuid: ParamUuid, value: Vec2) -> bool {
+        let exists = self.parameters.iter().any(|param| param.uuid == uuid);
+        self.set_params(&[(uuid, value)]);
+        exists
+    }
+
+    /// Sets the values of several parameters, then applies their bindings in one go.
+    ///
+    /// Every node driven by one of the updated parameters is reset and posed
+    /// a single time, no matter how many of the updates drive it.
+    /// All the parameters driving such a node are applied again,
+    /// see [`params`](crate::params) for how their bindings combine.
+    /// Updates for unknown parameters are ignored.
+    pub fn set_params(&mut self, updates: &[(ParamUuid, Vec2)]) {
+        let mut affected = BTreeSet::new();
+        for &(uuid, value) in updates {
+            let Some(param) = self.parameters.iter_mut().find(|param| param.uuid == uuid) else {
+                continue;
+            };
+            param.set_value(value);
+            affected.extend(param.bindings.iter().map(Binding::node));
+        }
+
+        if self.track_dirty_bounds {
+            // Where the nodes were drawn last, where they end up is computed in `dirty_bounds`
+            let newly_dirty: Vec<_> = affected.difference(&self.dirty).copied().collect();
+            self.dirty_bounds = self.union_bounds(self.dirty_bounds, &newly_dirty);
+        }
+
+        self.apply_bindings(&affected);
+        self.dirty.extend(affected);
+    }
+
+    /// Scales the whole puppet by `factor` around the origin of puppet space, e.g. for users to resize it.
+    ///
+    /// The scale applies on top of the transforms of all the nodes, root included,
+    /// so [`NodeTree::world_transform`], [`NodeTree::bounds`] and what gets rendered are scaled
+    /// while the rig is left as authored: node transforms, parameters and physics are unaffected.
+    /// Nodes locked to the root skip the transforms of their other ancestors, but are scaled all the same.
+    pub fn set_scale(&mut self, factor: f32) {
+        let root = self.nodes.arena[self.nodes.root]
+            .get()
+            .get_node_state()
+            .uuid;
+        if self.track_dirty_bounds && !self.dirty.contains(&root) {
+            self.dirty_bounds = self.union_bounds(self.dirty_bounds, &[root]);
+        }
+        self.nodes.scale = factor;
+        self.dirty.insert(root);
+    }
+
+    /// Scale of the whole puppet, 1 unless set with [`Puppet::set_scale`].
+    pub fn scale(&self) -> f32 {
+        self.nodes.scale
+    }
+
+    /// How far each vertex of the parts a parameter deforms would move with the parameter at `value`,
+    /// e.g. to overlay a heatmap of its influence. Parts are sorted by UUID.
+    ///
+    /// Only the deforms of that parameter are measured, in the space of the parts' meshes,
+    /// regardless of the other parameters. Nothing is applied to the puppet.
+    /// Returns nothing if the puppet has no parameter with that UUID.
+    pub fn binding_influence(&self, param: ParamUuid, value: Vec2) -> Vec<(NodeUuid, Vec<f32>)> {
+        let Some(param) = self.parameters.iter().find(|p| p.uuid == param) else {
+            return Vec::new();
+        };
+        param
+            .deforms_at(value)
+            .into_iter()
+            .map(|(uuid, deform)| (uuid, deform.iter().map(|delta| delta.length()).collect()))
+            .collect()
+    }
+
+    /// Captures the current values of all the parameters.
+    pub fn snapshot(&self) -> PoseSnapshot {
+        PoseSnapshot {
+            values: self
+                .parameters
+                .iter()
+                .map(|param| (param.uuid, param.value()))
+                .collect(),
+        }
+    }
+
+    /// Identifies the parameters of the puppet, for [encoded poses](crate::pose) to be checked against.
+    ///
+    /// Derived from the UUIDs of the parameters,
+    /// so it is the same for all copies of a puppet and for all runs of the program.
+    pub fn pose_id(&self) -> u64 {
+        let mut uuids: Vec<_> = self.parameters.iter().map(|param| param.uuid).collect();
+        uuids.sort();
+        let mut hasher = Fnv1a::new();
+        for uuid in uuids {
+            hasher.write_bytes(&uuid.as_u32().to_le_bytes());
+        }
+        hasher.finish()
+    }
+
+    /// Default values of all the parameters, e.g. to fade poses back to rest
+    /// or to place UI controls before anything is set.
+    ///
+    /// Unlike [`Puppet::snapshot`], this ignores the current values, and it doesn't change anything.
+    pub fn default_pose(&self) -> PoseSnapshot {
+        PoseSnapshot {
+            values: self
+                .parameters
+                .iter()
+                .map(|param| (param.uuid, param.defaults))
+                .collect(),
+        }
+    }
+
+    /// Restores the parameter values of a snapshot.
+    ///
+    /// Parameters the puppet doesn't have are skipped, so that a snapshot can be applied
+    /// to a puppet rigged differently than the one it was taken from.
+    /// Returns the number of parameters that were restored.
+    pub fn apply_snapshot(&mut self, snapshot: &PoseSnapshot) -> usize {
+        let updates: Vec<_> = snapshot
+            .values
+            .iter()
+            .map(|(&uuid, &value)| (uuid, value))
+            .filter(|&(uuid, _)| self.parameters.iter().any(|param| param.uuid == uuid))
+            .collect();
+        self.set_params(&updates);
+        updates.len()
+    }
+
+    /// Offsets the current value of a parameter by `delta`, then applies its bindings.
+    ///
+    /// The result is clamped to the parameter's range, like with [`Puppet::set_param`].
+    /// This lets animations be layered, e.g. a blink on top of a talk animation.
+    ///
+    /// Parameters driven by physics are overwritten with the simulation's output
+    /// on every physics update, so add to them after updating physics for the frame.
+    ///
+    /// Returns `false` if the puppet has no parameter with that UUID.
+    pub fn add_param(&mut self, uuid: ParamUuid, delta: Vec2) -> bool {
+        let Some(value) = self.get_param_by_uuid(uuid) else {
+            return false;
+        };
+        self.set_params(&[(uuid, value + delta)]);
+        true
+    }
+
+    /// Drawables in drawing order that would actually show up, for renderers to skip the others.
+    ///
+    /// Drawables are left out if they or one of their ancestors are disabled
+    /// (see [`NodeTree::is_render_enabled`]),
+    /// or if their [world opacity](NodeTree::world_opacity) is `epsilon` or less,
+    /// e.g. outfits toggled off by a parameter.
+    /// Parts with an [empty mesh](crate::mesh::Mesh::is_empty) aren't listed either, as they draw nothing,
+    /// and neither are the children of composites, as composites draw them.
+    pub fn visible_render_list(&self, epsilon: f32) -> Vec<NodeUuid> {
+        self.nodes
+            .zsorted()
+            .into_iter()
+            .filter(|&uuid| {
+                let empty_part = self.nodes.get_node(uuid).is_some_and(|node| {
+                    let part = node.as_any().downcast_ref::<Part>();
+                    part.is_some_and(|part| part.mesh.is_empty())
+                });
+                !empty_part
+                    && self.nodes.drawable(uuid).is_ok()
+                    && self.nodes.is_render_enabled(uuid)
+                    && self.nodes.world_opacity(uuid).unwrap_or(0.) > epsilon
+            })
+            .collect()
+    }
+
+    /// Parts that are enabled along with all their ancestors, in drawing order (see [`NodeTree::zsorted`]).
+    ///
+    /// Unlike [`Puppet::visible_render_list`], parts that are transparent, have an empty mesh
+    /// or are inside composites are included.
+    pub fn enabled_drawables(&self) -> impl Iterator<Item = (NodeUuid, &Part)> + '_ {
+        self.nodes.zsorted().into_iter().filter_map(|uuid| {
+            let node = self.nodes.get_node(uuid)?;
+            if node.kind() != NodeKind::Part || !self.nodes.is_render_enabled(uuid) {
+                return None;
+            }
+            Some((uuid, node.as_any().downcast_ref::<Part>()?))
+        })
+    }
+
+    fn parts(&self) -> impl Iterator<Item = (NodeUuid, &Part)> {
+        self.nodes
+            .iter()
+            .filter_map(|(uuid, node)| Some((uuid, node.as_any().downcast_ref::<Part>()?)))
+    }
+
+    /// Number of vertices in the meshes of all the parts.
+    pub fn vertex_count(&self) -> usize {
+        self.parts().map(|(_, part)| part.mesh.vertices.len()).sum()
+    }
+
+    /// Number of triangles in the meshes of all the parts, see [`Mesh::triangle_count`](crate::mesh::Mesh::triangle_count).
+    pub fn triangle_count(&self) -> usize {
+        self.parts()
+            .map(|(_, part)| part.mesh.triangle_count())
+            .sum()
+    }
+
+    /// The `n` parts with the most triangles, with their triangle count, most triangles first.
+    pub fn heaviest_parts(&self, n: usize) -> Vec<(NodeUuid, usize)> {
+        let mut parts: Vec<_> = self
+            .parts()
+            .map(|(uuid, part)| (uuid, part.mesh.triangle_count()))
+            .collect();
+        parts.sort_by_key(|&(_, triangles)| std::cmp::Reverse(triangles));
+        parts.truncate(n);
+        parts
+    }
+
+    /// Enables or disables every drawable of a group, e.g. to swap outfits.
+    ///
+    /// Returns the number of drawables in the group.
+    pub fn toggle_group(&mut self, group_id: &str, enabled: bool) -> usize {
+        let members: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(uuid, _)| uuid)
+            .filter(|&uuid| {
+                self.nodes
+                    .drawable(uuid)
+                    .is_ok_and(|draw_state| draw_state.group_id.as_deref() == Some(group_id))
+            })
+            .collect();
+        for &uuid in &members {
+            if let Some(node) = self.nodes.get_node_mut(uuid) {
+                node.get_node_state_mut().enabled = enabled;
+            }
+        }
+        members.len()
+    }
+
+    /// Convex hulls of the parts for which `tagged` returns `true`, e.g. to make hitboxes.
+    ///
+    /// Hulls are computed from the rest vertices of the parts (see [`Part::convex_hull`]),
+    /// then moved to puppet space with the current transforms, deforms aren't taken into account.
+    pub fn collision_hulls(&self, tagged: impl Fn(&Part) -> bool) -> BTreeMap<NodeUuid, Vec<Vec2>> {
+        self.nodes
+            .iter()
+            .filter_map(|(uuid, node)| {
+                let part = node.as_any().downcast_ref::<Part>()?;
+                if !tagged(part) {
+                    return None;
+                }
+                let matrix = part.matrix(self.nodes.parent_matrix(uuid)?);
+                let hull = part
+                    .convex_hull()
+                    .into_iter()
+                    .map(|vertex| matrix.transform_point3(vertex.extend(0.)).truncate())
+                    .collect();
+                Some((uuid, hull))
+            })
+            .collect()
+    }
+
+    /// Registers an animation under its name, replacing any animation with the same name.
+    ///
+    /// Lanes animating parameters that the puppet doesn't have are kept but do nothing,
+    /// so that animations shipped separately from the model still load.
+    /// Returns the UUIDs of these parameters, see [`Animation::check_against`] for a full report.
+    pub fn attach_animation(&mut self, animation: Animation) -> Vec<ParamUuid> {
+        let unresolved = animation.check_against(self).unresolved_params;
+        #[cfg(feature = "tracing")]
+        if !unresolved.is_empty() {
+            tracing::warn!(
+                animation = animation.name,
+                "Animation targets unknown parameters: {unresolved:?}"
+            );
+        }
+        self.animations.insert(animation.name.clone(), animation);
+        unresolved
+    }
+
+    /// Enables and disables nodes according to the [`EnabledTrack`](crate::animation::EnabledTrack)s of an animation at `frame`.
+    ///
+    /// Tracks of nodes that the puppet doesn't have do nothing.
+    /// Returns `false` if the puppet has no animation with that name.
+    pub fn apply_enabled_tracks(&mut self, animation: &str, frame: f32) -> bool {
+        let Some(animation) = self.animations.get(animation) else {
+            return false;
+        };
+        for track in &animation.enabled_tracks {
+            let Some(enabled) = track.sample(frame) else {
+                continue;
+            };
+            if let Some(node) = self.nodes.get_node_mut(track.node) {
+                node.get_node_state_mut().enabled = enabled;
+            }
+        }
+        true
+    }
+
+    /// Advances the simulation of the physics nodes by `dt` seconds,
+    /// in the environment given by [`Puppet::physics`], then applies their output parameters.
+    ///
+    /// Call it once per frame after setting the parameters that move the physics nodes,
+    /// as the simulation reacts to where they ended up.
+    /// Nodes moved by the output of other physics nodes are simulated after them,
+    /// see [`Puppet::validate_driver_graph`].
+    ///
+    /// Nothing happens if `dt` isn't a positive number of seconds, e.g. when paused with a `dt` of 0,
+    /// and very short frames are simulated as slightly longer ones.
+    pub fn update_physics(&mut self, dt: f32) {
+        if !is_valid_dt(dt) {
+            return;
+        }
+        for uuid in DriverGraph::new(&self.nodes, &self.parameters).order() {
+            let Some(physics) = self
+                .nodes
+                .get_node(uuid)
+                .and_then(|node| node.as_any().downcast_ref::<SimplePhysics>())
+            else {
+                continue;
+            };
+            // Simulated as rigged, so that scaling the puppet doesn't change how it swings
+            let Some(anchor) = self.nodes.rig_matrix(uuid) else {
+                continue;
+            };
+            let anchor = anchor.w_axis.truncate().truncate();
+            let state = self
+                .physics_state
+                .entry(uuid)
+                .or_insert_with(|| physics.rest_state(anchor));
+            let Some(value) = physics.step(state, anchor, &self.physics, dt) else {
+                continue;
+            };
+            self.set_params(&[(physics.param(), value)]);
+        }
+    }
+
+    /// Checks that no physics node ends up moving itself through the parameters of physics nodes,
+    /// which makes the simulation feed back into itself and oscillate.
+    ///
+    /// A physics node moves another when its output parameter drives the other node or one of its ancestors.
+    /// Returns the groups of physics nodes that move each other.
+    pub fn validate_driver_graph(&self) -> Result<(), Vec<Cycle>> {
+        let cycles = DriverGraph::new(&self.nodes, &self.parameters).cycles();
+        if cycles.is_empty() {
+            Ok(())
+        } else {
+            Err(cycles)
+        }
+    }
+
+    /// Puts all the physics nodes back at rest, e.g. after the puppet jumped to another place.
+    pub fn reset_physics(&mut self) {
+        self.physics_state.clear();
+    }
+
+    /// Dissolves the composites that are just organizational groups into their parent,
+    /// so that their children render inline in zsort order.
+    ///
+    /// A composite is dissolved when rendering it offscreen makes no difference:
+    /// it uses the Normal blend mode at full opacity with no tint and no masks,
+    /// it isn't rotated or scaled, and no parameter drives it.
+    /// Its translation and zsort are moved over to its children.
+    ///
+    /// Composites whose translation and zsort can't be moved over that way are kept:
+    /// those that are locked to the root, snapped to pixels, offset or with an overridden zsort,
+    /// those with children that are locked to the root or have an overridden zsort,
+    /// those other drawables use as a mask,
+    /// and disabled ones, which hide their children.
+    pub fn flatten_composites(&mut self) -> FlattenReport {
+        let composites: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.as_any().is::<Composite>())
+            .map(|(uuid, _)| uuid)
+            .collect();
+        let mask_sources: BTreeSet<_> = self
+            .nodes
+            .iter()
+            .filter_map(|(_, node)| node.as_drawable())
+            .flat_map(|drawable| drawable.masks().iter().map(|mask| mask.source))
+            .collect();
+
+        let mut report = FlattenReport::default();
+        for uuid in composites {
+            let driven = self
+                .parameters
+                .iter()
+                .any(|param| param.bindings.iter().any(|binding| binding.node() == uuid));
+            let children = self.nodes.get_children_uuids(uuid).unwrap_or_default();
+            let pinned_children = children.iter().any(|&child| {
+                self.nodes.get_node(child).is_some_and(|child| {
+                    let state = child.get_node_state();
+                    state.lock_to_root || state.zsort_override.is_some()
+                })
+            });
+
+            let composite = self
+                .nodes
+                .get_node(uuid)
+                .and_then(|node| node.as_any().downcast_ref::<Composite>());
+            let Some(composite) = composite.filter(|composite| {
+                !driven
+                    && !pinned_children
+                    && !mask_sources.contains(&uuid)
+                    && is_plain_group(composite)
+            }) else {
+                report.kept.push(uuid);
+                continue;
+            };
+
+            let translation = composite.node_state.transform.translation;
+            let zsort = composite.node_state.zsort;
+            for child in children {
+                if let Some(child) = self.nodes.get_node_mut(child) {
+                    let state = child.get_node_state_mut();
+                    state.transform.translation += translation;
+                    state.zsort += zsort;
+                }
+            }
+
+            if self.nodes.remove_node(uuid) {
+                report.flattened.push(uuid);
+            } else {
+                report.kept.push(uuid);
+            }
+        }
+
+        report
+    }
+
+    /// Recomputes the offsets of the `targets` from the bindings of every parameter.
+    fn apply_bindings(&mut self, targets: &BTreeSet<NodeUuid>) {
+        for &uuid in targets {
+            let Some(node) = self.nodes.get_node_mut(uuid) else {
+                continue;
+            };
+            node.get_node_state_mut().reset_offsets();
+            if let Some(part) = node.as_any_mut().downcast_mut::<Part>() {
+                part.reset_deform();
+            }
+            if let Ok(draw_state) = self.nodes.drawable_mut(uuid) {
+                draw_state.offset_opacity = 1.;
+            }
+        }
+
+        // Overrides last, so that they replace what the additive bindings did
+        for mode in [MergeMode::Additive, MergeMode::Override] {
+            for param in &self.parameters {
+                param.apply_bindings(&mut self.nodes, targets, mode);
+            }
+        }
+        self.nodes.deform_children(targets);
+    }
+
+    /// Makes a new puppet out of a node and its descendants, e.g. to render a preview of a single part.
+    ///
+    /// The node becomes the root of the new puppet, so the transforms of its ancestors are dropped.
+    /// Only the parameters driving one of the extracted nodes are kept, with just those bindings.
+    /// Masks and welded links pointing outside of the extracted nodes are removed.
+    ///
+    /// Parts still use the texture indices of the original model,
+    /// see [`Model::extract_subtree`](crate::model::Model::extract_subtree) to remap them.
+    pub fn extract_subtree(&self, uuid: NodeUuid) -> Option<Puppet> {
+        let mut nodes = self.nodes.subtree(uuid)?;
+        let kept: BTreeSet<_> = nodes.uuids.keys().copied().collect();
+
+        for (_, node) in nodes.iter_mut() {
+            let node = node.as_any_mut();
+            let draw_state = if let Some(part) = node.downcast_mut::<Part>() {
+                &mut part.draw_state
+            } else if let Some(composite) = node.downcast_mut::<Composite>() {
+                &mut composite.draw_state
+            } else {
+                continue;
+            };
+            draw_state.masks.retain(|mask| kept.contains(&mask.source));
+            draw_state
+                .welded_links
+                .retain(|link| kept.contains(&link.target));
+        }
+
+        let parameters = self
+            .parameters
+            .iter()
+            .filter(|param| param.bindings.iter().any(|b| kept.contains(&b.node())))
+            .map(|param| {
+                let mut param = param.clone();
+                param.bindings.retain(|b| kept.contains(&b.node()));
+                param
+            })
+            .collect();
+
+        let mut puppet = Puppet::new(self.meta.clone(), self.physics.clone(), nodes, parameters);
+        puppet.track_dirty_bounds = self.track_dirty_bounds;
+        puppet.apply_bindings(&kept);
+        Some(puppet)
+    }
+
+    /// Nodes whose offsets changed since the last call to [`Puppet::clear_dirty`].
+    pub fn dirty_nodes(&self) -> &BTreeSet<NodeUuid> {
+        &self.dirty
+    }
+
+    /// Enables or disables tracking of [`Puppet::dirty_bounds`], which is disabled by default.
+    ///
+    /// Tracking it makes parameter updates slower, as the parts have to be measured.
+    pub fn set_track_dirty_bounds(&mut self, track: bool) {
+        self.track_dirty_bounds = track;
+    }
+
+    /// Area of the puppet that changed since the last call to [`Puppet::clear_dirty`], in puppet space.
+    ///
+    /// It covers the parts among the dirty nodes and their descendants,
+    /// both where they were and where they ended up. Any parameter update counts,
+    /// including the ones made by physics.
+    ///
+    /// Returns `None` if nothing visible changed,
+    /// or if it isn't tracked (see [`Puppet::set_track_dirty_bounds`]).
+    pub fn dirty_bounds(&self) -> Option<Rect> {
+        if !self.track_dirty_bounds {
+            return None;
+        }
+        let dirty: Vec<_> = self.dirty.iter().copied().collect();
+        self.union_bounds(self.dirty_bounds, &dirty)
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty_bounds = None;
+    }
+
+    /// Union of `bounds` and the current bounds of the nodes.
+    fn union_bounds(&self, bounds: Option<Rect>, nodes: &[NodeUuid]) -> Option<Rect> {
+        nodes
+            .iter()
+            .filter_map(|&uuid| self.nodes.bounds(uuid))
+            .chain(bounds)
+            .reduce(Rect::union)
+    }
+}
+
+/// Whether rendering the composite offscreen would look the same as rendering its children inline,
+/// with its translation and zsort added to theirs.
+fn is_plain_group(composite: &Composite) -> bool {
+    let draw_state = &composite.draw_state;
+    let state = &composite.node_state;
+    let transform = &state.transform;
+    state.enabled
+        && !state.lock_to_root
+        && state.zsort_override.is_none()
+        && state.offset_transform == Transform::default()
+        && state.offset_zsort == 0.
+        && !transform.pixel_snap
+        && draw_state.blend_mode == BlendMode::Normal
+        && draw_state.opacity >= 1.
+        && draw_state.tint == Vec3::ONE
+        && draw_state.screen_tint == Vec3::ZERO
+        && draw_state.masks.is_empty()
+        && transform.rotation == Vec3::ZERO
+        && transform.scale == Vec2::ONE
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::mesh::Mesh;
+    use crate::model::ModelTexture;
+    use crate::nodes::drawable::{DrawState, Mask, MaskMode};
+    use crate::nodes::node::NodeState;
+
+    fn composite(uuid: u32, translation: Vec3) -> Composite {
+        let mut node_state = NodeState::new(NodeUuid(uuid), format!("Composite {uuid}"));
+        node_state.transform.translation = translation;
+        node_state.zsort = 1.;
+        Composite {
+            node_state,
+            draw_state: DrawState::default(),
+            propagate_mesh_group: true,
+            do_not_mask: false,
+        }
+    }
+
+    fn part(uuid: u32) -> Part {
+        let node_state = NodeState::new(NodeUuid(uuid), format!("Part {uuid}"));
+        Part::new(node_state, Mesh::quad().size(8, 8).build(), [0; 3])
+    }
+
+    fn world_translation(puppet: &Puppet, uuid: u32) -> Vec3 {
+        let transform = puppet.nodes.world_transform(NodeUuid(uuid)).unwrap();
+        transform.translation
+    }
+
+    #[test]
+    fn ndc_matrix_flips_y() {
+        let ndc = Puppet::to_ndc_matrix((200., 100.));
+        let to_ndc = |x, y| ndc.transform_point3(vec3(x, y, 0.)).truncate();
+
+        assert_eq!(to_ndc(0., 0.), Vec2::ZERO);
+        // Puppet space points down, NDC up
+        assert_eq!(to_ndc(0., 50.), Vec2::new(0., -1.));
+        assert_eq!(to_ndc(0., -50.), Vec2::new(0., 1.));
+        assert_eq!(to_ndc(-100., -50.), Vec2::new(-1., 1.));
+        assert_eq!(to_ndc(100., 50.), Vec2::new(1., -1.));
+
+        // Zoomed in twice on (10, 20), the bottom of the viewport is 25 pixels below it
+        let camera = Camera {
+            position: Vec2::new(10., 20.),
+            zoom: 2.,
+        };
+        let framed = camera.matrix((200., 100.));
+        let point = framed.transform_point3(vec3(10., 45., 0.)).truncate();
+        assert_eq!(point, Vec2::new(0., -1.));
+    }
+
+    #[test]
+    fn camera_falls_back_to_fitting_the_bounds() {
+        let root = NodeUuid(1);
+        let builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        assert_eq!(builder.build().puppet.camera((200., 100.)), None);
+
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder.add_texture(ModelTexture {
+            format: image::ImageFormat::Png,
+            data: Vec::new(),
+        });
+        builder.add_node(root, part(2)).unwrap();
+        let mut puppet = builder.build().puppet;
+        let bounds = puppet.nodes.bounds(root).unwrap();
+        let fitted = puppet.camera((200., 100.)).unwrap();
+        assert_eq!(fitted.position, (bounds.min + bounds.max) / 2.);
+        let size = bounds.size();
+        assert_eq!(fitted.zoom, (200. / size.x).min(100. / size.y));
+
+        let camera = Camera {
+            position: Vec2::new(10., 20.),
+            zoom: 2.,
+        };
+        puppet.meta.default_camera = Some(camera);
+        assert_eq!(puppet.camera((200., 100.)), Some(camera));
+    }
+
+    #[test]
+    fn empty_part_groups_a_real_part() {
+        let root = NodeUuid(1);
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder.add_texture(ModelTexture {
+            format: image::ImageFormat::Png,
+            data: Vec::new(),
+        });
+        let mut group = NodeState::new(NodeUuid(2), "Group");
+        group.transform.translation = vec3(10., 5., 0.);
+        builder
+            .add_part(root, group, Mesh::default(), [0; 3])
+            .unwrap();
+        builder.add_node(NodeUuid(2), part(3)).unwrap();
+        let puppet = builder.build().puppet;
+
+        assert_eq!(puppet.visible_render_list(0.), [NodeUuid(3)]);
+        let scene: Vec<_> = puppet.scene().into_iter().map(|item| item.uuid).collect();
+        assert_eq!(scene, [NodeUuid(3)]);
+        let world = puppet.nodes.world_transform(NodeUuid(3)).unwrap();
+        assert_eq!(world.translation, vec3(10., 5., 0.));
+        assert_eq!(puppet.nodes.baked_verts(NodeUuid(2)), Some(Vec::new()));
+    }
+
+    #[test]
+    fn duplicate_params_are_reported_then_dropped() {
+        let mut puppet = ModelBuilder::new(NodeState::new(NodeUuid(1), "Root"))
+            .build()
+            .puppet;
+        let param = |uuid, name: &str, max| {
+            Param::new(ParamUuid(uuid), name, false, Vec2::ZERO, Vec2::new(max, 0.))
+        };
+        puppet.parameters = vec![
+            param(1, "Head", 1.),
+            param(2, "Eyes", 1.),
+            param(1, "Mouth", 1.),
+            param(3, "Head", 2.),
+            // Only reported for its UUID
+            param(1, "Head", 3.),
+        ];
+
+        let duplicate_name = |index| ParamConflict::DuplicateName {
+            index,
+            uuid: ParamUuid(3),
+            first: ParamUuid(1),
+            name: "Head".to_owned(),
+        };
+        assert_eq!(
+            puppet.param_conflicts(),
+            [
+                ParamConflict::DuplicateUuid {
+                    index: 2,
+                    uuid: ParamUuid(1)
+                },
+                duplicate_name(3),
+                ParamConflict::DuplicateUuid {
+                    index: 4,
+                    uuid: ParamUuid(1)
+                },
+            ]
+        );
+        // The first parameter with a name wins
+        puppet.set_param(ParamUuid(1), Vec2::new(5., 0.));
+        assert_eq!(puppet.get_param("Head"), Some(Vec2::new(1., 0.)));
+
+        let dropped: Vec<_> = puppet
+            .drop_duplicate_params()
+            .into_iter()
+            .map(|param| param.name)
+            .collect();
+        assert_eq!(dropped, ["Mouth", "Head"]);
+        let uuids: Vec<_> = puppet.parameters.iter().map(|param| param.uuid).collect();
+        assert_eq!(uuids, [1, 2, 3].map(ParamUuid));
+        assert_eq!(puppet.param_conflicts(), [duplicate_name(2)]);
+    }
+
+    #[test]
+    fn flatten_keeps_composites_it_cant_dissolve() {
+        let root = NodeUuid(1);
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder.add_texture(ModelTexture {
+            format: image::ImageFormat::Png,
+            data: Vec::new(),
+        });
+        let offset = vec3(10., 5., 0.);
+
+        // Plain group
+        builder.add_node(root, composite(2, offset)).unwrap();
+        builder.add_node(NodeUuid(2), part(3)).unwrap();
+        // Group with a child locked to the root
+        builder.add_node(root, composite(4, offset)).unwrap();
+        let mut locked = part(5);
+        locked.node_state.lock_to_root = true;
+        builder.add_node(NodeUuid(4), locked).unwrap();
+        // Group with a child with an overridden zsort
+        builder.add_node(root, composite(6, offset)).unwrap();
+        let mut pinned = part(7);
+        pinned.node_state.zsort_override = Some(3.);
+        builder.add_node(NodeUuid(6), pinned).unwrap();
+        // Group used as a mask
+        builder.add_node(root, composite(8, offset)).unwrap();
+        builder.add_node(NodeUuid(8), part(9)).unwrap();
+        let mut masked = part(10);
+        masked.draw_state.masks.push(Mask {
+            source: NodeUuid(8),
+            mode: MaskMode::Mask,
+            threshold: None,
+        });
+        builder.add_node(root, masked).unwrap();
+        // Snapped group
+        let mut snapped = composite(11, offset);
+        snapped.node_state.transform.pixel_snap = true;
+        builder.add_node(root, snapped).unwrap();
+        builder.add_node(NodeUuid(11), part(12)).unwrap();
+        // Disabled group
+        let mut disabled = composite(13, offset);
+        disabled.node_state.enabled = false;
+        builder.add_node(root, disabled).unwrap();
+        builder.add_node(NodeUuid(13), part(14)).unwrap();
+
+        let mut puppet = builder.build().puppet;
+        let parts = [3, 5, 7, 9, 10, 12, 14];
+        let before: Vec<_> = parts
+            .iter()
+            .map(|&uuid| world_translation(&puppet, uuid))
+            .collect();
+        let zsorted = puppet.nodes.zsorted();
+
+        let mut report = puppet.flatten_composites();
+        report.kept.sort();
+        assert_eq!(report.flattened, [NodeUuid(2)]);
+        assert_eq!(report.kept, [4, 6, 8, 11, 13].map(NodeUuid));
+        assert!(!puppet.visible_render_list(0.).contains(&NodeUuid(14)));
+
+        let after: Vec<_> = parts
+            .iter()
+            .map(|&uuid| world_translation(&puppet, uuid))
+            .collect();
+        assert_eq!(after, before);
+        // The part takes the place of its group, which drew it
+        let zsorted: Vec<_> = zsorted
+            .into_iter()
+            .map(|uuid| {
+                if uuid == NodeUuid(2) {
+                    NodeUuid(3)
+                } else {
+                    uuid
+                }
+            })
+            .collect();
+        assert_eq!(puppet.nodes.zsorted(), zsorted);
+    }
 }
@@ -1,4 +1,5 @@
 use std::any::TypeId;
+use std::borrow::Borrow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 
@@ -13,7 +14,7 @@ use crate::nodes::part::Part;
 
 use self::node_renderers::composite_renderer::CompositeRenderer;
 use self::node_renderers::part_renderer::PartRenderer;
-use self::texture::load_texture;
+use self::texture::{load_texture, placeholder_texture, set_texture_filter};
 use self::vbo::Vbo;
 
 pub mod node_renderers;
@@ -99,13 +100,20 @@ pub struct OpenglRenderer {
     pub verts: Vbo<f32>,
     pub uvs: Vbo<f32>,
     pub deform: Vbo<f32>,
-    pub ibo: Vbo<u16>,
+    pub ibo: Vbo<u32>,
     pub textures: Vec<glow::NativeTexture>,
     pub node_renderers: HashMap<TypeId, ErasedNodeRenderer>,
 }
 
 impl OpenglRenderer {
-    pub fn new(gl: glow::Context, mut nodes: NodeTree, textures: Vec<ModelTexture>) -> Self {
+    /// Creates a renderer for the nodes, uploading the textures.
+    ///
+    /// The textures can be owned, borrowed or shared (see [`SharedModel`](crate::model::SharedModel)).
+    pub fn new<T: Borrow<ModelTexture>>(
+        gl: glow::Context,
+        mut nodes: NodeTree,
+        textures: impl IntoIterator<Item = T>,
+    ) -> Self {
         let vao = unsafe { gl.create_vertex_array() }.unwrap();
 
         let mut verts = Vbo::from(vec![-1., -1., -1., 1., 1., -1., 1., -1., -1., 1., 1., 1.]);
@@ -122,20 +130,30 @@ impl OpenglRenderer {
                 let num_verts = smesh.vertices.0.len();
                 assert_eq!(num_verts, smesh.uvs.0.len());
 
-                node.start_indice = ibo.len() as u16;
+                node.start_indice = ibo.len() as u32;
                 // node.start_deform = current_ibo_offset * 2;
 
                 verts.extend_from_slice(smesh.vertices.0.as_slice());
                 uvs.extend_from_slice(smesh.uvs.0.as_slice());
                 deform.extend_from_slice(vec![0.; num_verts].as_slice());
                 ibo.extend(smesh.indices.iter().map(|index| index + current_ibo_offset));
-                current_ibo_offset += (num_verts / 2) as u16;
+                current_ibo_offset += (num_verts / 2) as u32;
             }
         }
 
         let textures: Vec<_> = textures
-            .iter()
-            .map(|texture| load_texture(&gl, &texture.data))
+            .into_iter()
+            .map(|texture| {
+                let texture = texture.borrow();
+                load_texture(&gl, texture).unwrap_or_else(|_error| {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        format = ?texture.format,
+                        "Could not load a texture, drawing it transparent: {_error}"
+                    );
+                    placeholder_texture(&gl)
+                })
+            })
             .collect();
 
         let part_renderer = PartRenderer::new(&gl);
@@ -277,6 +295,16 @@ impl OpenglRenderer {
         }
     }
 
+    /// Switches the puppet's textures between nearest-neighbor and linear filtering.
+    ///
+    /// Pass the puppet's [`PuppetMeta::preserve_pixels`](crate::puppet::PuppetMeta::preserve_pixels).
+    pub fn set_preserve_pixels(&self, preserve_pixels: bool) {
+        for &texture in &self.textures {
+            self.bind_texture(texture);
+            unsafe { set_texture_filter(&self.gl, preserve_pixels) };
+        }
+    }
+
     pub fn clear(&self) {
         unsafe { self.gl.clear(glow::COLOR_BUFFER_BIT) };
     }
@@ -102,8 +102,7 @@ impl CompositeRenderer {
             gl.clear(glow::COLOR_BUFFER_BIT);
             let children = renderer
                 .nodes
-                .get_children_uuids(node.get_node_state().uuid)
-                .unwrap_or_default();
+                .composite_render_list(node.get_node_state().uuid);
             renderer.render_nodes(&children);
 
             gl.bind_framebuffer(glow::FRAMEBUFFER, None);
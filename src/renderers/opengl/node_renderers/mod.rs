@@ -1,2 +1,2 @@
+pub mod composite_renderer;
 pub mod part_renderer;
-pub mod composite_renderer;
\ No newline at end of file
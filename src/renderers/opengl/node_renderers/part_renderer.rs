@@ -1,6 +1,5 @@
 use glow::HasContext;
 
-use crate::nodes::drawable::Mask;
 use crate::nodes::node::Node;
 use crate::nodes::part::Part;
 use crate::renderers::opengl::NodeRenderer;
@@ -25,21 +24,26 @@ void main() {
 const FRAGMENT: &str = "#version 100
 precision mediump float;
 uniform sampler2D texture;
+uniform float threshold;
 varying vec2 texcoord;
 
 void main() {
     vec4 color = texture2D(texture, texcoord);
-    if (color.a < 0.05) {
+    if (color.a <= threshold) {
         discard;
     }
     gl_FragColor = color;
 }
 ";
 
+/// Alpha cutoff for parts that aren't being rendered as a mask.
+const DEFAULT_THRESHOLD: f32 = 0.05;
+
 #[derive(Debug, Clone)]
 pub(crate) struct PartRenderer {
     part_program: glow::NativeProgram,
     u_trans: Option<glow::NativeUniformLocation>,
+    u_threshold: Option<glow::NativeUniformLocation>,
 }
 
 impl NodeRenderer for PartRenderer {
@@ -58,7 +62,7 @@ impl NodeRenderer for PartRenderer {
 
         eprintln!("  Rendering part {name}");
         renderer.set_stencil(false);
-        self.render_part(renderer, node);
+        self.render_part(renderer, node, DEFAULT_THRESHOLD);
 
         unsafe { gl.pop_debug_group() };
     }
@@ -68,21 +72,32 @@ impl PartRenderer {
     pub(crate) fn new(gl: &glow::Context) -> Self {
         let part_program = shader::compile(gl, VERTEX, FRAGMENT).unwrap();
         let u_trans = unsafe { gl.get_uniform_location(part_program, "trans") };
+        let u_threshold = unsafe { gl.get_uniform_location(part_program, "threshold") };
 
         Self {
             part_program,
             u_trans,
+            u_threshold,
         }
     }
 
-    fn render_part(&self, renderer: &OpenglRenderer, node: &Part) {
+    fn render_part(&self, renderer: &OpenglRenderer, node: &Part, threshold: f32) {
+        // Parts with a missing texture are reported by `Model::validate`, just skip them
+        let Some(&texture) = renderer.textures.get(node.textures[0]) else {
+            return;
+        };
+        // Parts only used to group others have nothing to draw
+        if node.mesh.is_empty() {
+            return;
+        }
+
         renderer.use_program(self.part_program);
 
         if !node.draw_state.masks.is_empty() {
-            self.recompute_masks(renderer, &node.draw_state.masks);
+            self.recompute_masks(renderer, node);
         }
 
-        renderer.bind_texture(renderer.textures[node.textures[0]]);
+        renderer.bind_texture(texture);
         renderer.set_blend_mode(node.draw_state.blend_mode);
 
         let trans = self.trans(renderer, node);
@@ -90,17 +105,19 @@ impl PartRenderer {
         let gl = &renderer.gl;
         unsafe {
             gl.uniform_2_f32(self.u_trans.as_ref(), trans.x, trans.y);
+            gl.uniform_1_f32(self.u_threshold.as_ref(), threshold);
 
             gl.draw_elements(
                 glow::TRIANGLES,
                 node.num_indices() as i32,
-                glow::UNSIGNED_SHORT,
-                (node.start_indice as i32) * 2,
+                glow::UNSIGNED_INT,
+                (node.start_indice as i32) * 4,
             );
         }
     }
 
-    fn recompute_masks(&self, renderer: &OpenglRenderer, masks: &[Mask]) {
+    fn recompute_masks(&self, renderer: &OpenglRenderer, node: &Part) {
+        let masks = node.draw_state.masks.as_slice();
         if renderer.gl_cache.borrow().prev_masks == masks {
             return;
         }
@@ -117,7 +134,7 @@ impl PartRenderer {
         for mask in masks.iter() {
             let mask_node = renderer.nodes.get_node(mask.source).unwrap();
             if let Some(part) = mask_node.as_any().downcast_ref() {
-                self.render_part(renderer, part);
+                self.render_part(renderer, part, node.draw_state.threshold_for(mask));
             }
         }
 
@@ -131,14 +148,25 @@ impl PartRenderer {
     }
 
     fn trans(&self, renderer: &OpenglRenderer, node: &Part) -> glam::Vec3 {
-        let mut trans = node.node_state.transform.translation;
-
-        for ancestor in renderer.nodes.ancestors(node.node_state.uuid).skip(1) {
-            if let Some(node) = renderer.nodes.arena.get(ancestor) {
-                trans += node.get().get_node_state().transform.translation;
+        let mut trans = node.node_state.local_transform().translation;
+
+        let nodes = &renderer.nodes;
+        let mut locked = node.node_state.lock_to_root;
+        for ancestor in nodes.ancestors(node.node_state.uuid).skip(1) {
+            // Nodes locked to the root skip their other ancestors
+            if locked && ancestor != nodes.root {
+                continue;
+            }
+            if let Some(node) = nodes.arena.get(ancestor) {
+                let state = node.get().get_node_state();
+                trans += state.local_transform().translation;
+                locked = state.lock_to_root;
             }
         }
 
+        if node.node_state.transform.pixel_snap {
+            trans = trans.round();
+        }
         trans
     }
 }
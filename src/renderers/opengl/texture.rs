@@ -1,4 +1,7 @@
 use glow::HasContext;
+use image::ImageError;
+
+use crate::model::ModelTexture;
 
 /// Uploads a texture to OpenGL.
 ///
@@ -18,16 +21,7 @@ pub(crate) unsafe fn upload_texture(
 ) -> glow::NativeTexture {
     let texture = gl.create_texture().unwrap();
     gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-    gl.tex_parameter_i32(
-        glow::TEXTURE_2D,
-        glow::TEXTURE_MIN_FILTER,
-        glow::LINEAR as i32,
-    );
-    gl.tex_parameter_i32(
-        glow::TEXTURE_2D,
-        glow::TEXTURE_MAG_FILTER,
-        glow::LINEAR as i32,
-    );
+    set_texture_filter(gl, false);
     gl.tex_image_2d(
         glow::TEXTURE_2D,
         0,
@@ -42,16 +36,30 @@ pub(crate) unsafe fn upload_texture(
     texture
 }
 
-pub(crate) fn load_texture(gl: &glow::Context, tex: &[u8]) -> glow::NativeTexture {
-    match image::load_from_memory_with_format(tex, image::ImageFormat::Tga).unwrap() {
-        image::DynamicImage::ImageRgba8(ref image) => {
-            let (width, height) = image.dimensions();
-            unsafe { upload_texture(gl, width, height, glow::RGBA, Some(image)) }
-        }
-        image::DynamicImage::ImageRgb8(ref image) => {
-            let (width, height) = image.dimensions();
-            unsafe { upload_texture(gl, width, height, glow::RGB, Some(image)) }
-        }
-        image => todo!("Unsupported image: {:?}", image),
-    }
+/// Sets the min/mag filters of the currently bound texture.
+///
+/// Pixel-art textures use nearest-neighbor sampling so they don't get blurry.
+pub(crate) unsafe fn set_texture_filter(gl: &glow::Context, preserve_pixels: bool) {
+    let filter = if preserve_pixels {
+        glow::NEAREST
+    } else {
+        glow::LINEAR
+    };
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, filter as i32);
+    gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, filter as i32);
+}
+
+/// Decodes a texture with [`ModelTexture::decode`], in its own format and origin, and uploads it to OpenGL.
+pub(crate) fn load_texture(
+    gl: &glow::Context,
+    texture: &ModelTexture,
+) -> Result<glow::NativeTexture, ImageError> {
+    let image = texture.decode()?;
+    let (width, height) = image.dimensions();
+    Ok(unsafe { upload_texture(gl, width, height, glow::RGBA, Some(&image)) })
+}
+
+/// Transparent 1x1 texture, drawn in place of the textures that couldn't be loaded.
+pub(crate) fn placeholder_texture(gl: &glow::Context) -> glow::NativeTexture {
+    unsafe { upload_texture(gl, 1, 1, glow::RGBA, Some(&[0; 4])) }
 }
@@ -0,0 +1,124 @@
+use glam::Mat4;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::nodes::composite::Composite;
+use crate::nodes::drawable::{BlendMode, DrawState};
+use crate::nodes::node::NodeUuid;
+use crate::nodes::node_tree::NodeTree;
+use crate::nodes::part::Part;
+use crate::puppet::Puppet;
+
+/// A drawable in the current pose, as listed by [`Puppet::scene`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DrawItem {
+    pub uuid: NodeUuid,
+    /// Matrix mapping the drawable's vertices to puppet space, in column-major order when serialized.
+    ///
+    /// For parts, it rotates and scales around the mesh origin like [`Part::baked_verts`].
+    pub matrix: Mat4,
+    /// Albedo, emissive and bump texture indices, or `None` for composites.
+    pub textures: Option<[usize; 3]>,
+    pub blend_mode: BlendMode,
+    pub opacity: f32,
+    /// Items drawn by a composite into its own buffer, in drawing order.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub children: Vec<DrawItem>,
+}
+
+impl DrawItem {
+    fn new(uuid: NodeUuid, matrix: Mat4, draw_state: &DrawState) -> Self {
+        Self {
+            uuid,
+            matrix,
+            textures: None,
+            blend_mode: draw_state.blend_mode,
+            opacity: draw_state.local_opacity(),
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Items for the drawables among `uuids` that are enabled along with their ancestors, in the same order.
+fn draw_items(nodes: &NodeTree, uuids: &[NodeUuid]) -> Vec<DrawItem> {
+    uuids
+        .iter()
+        .filter_map(|&uuid| {
+            let node = nodes.get_node(uuid)?;
+            if !nodes.is_render_enabled(uuid) {
+                return None;
+            }
+
+            if let Some(part) = node.as_any().downcast_ref::<Part>() {
+                if part.mesh.is_empty() {
+                    return None;
+                }
+                let matrix = part.matrix(nodes.parent_matrix(uuid)?);
+                let mut item = DrawItem::new(uuid, matrix, &part.draw_state);
+                item.textures = Some(part.textures);
+                Some(item)
+            } else if let Some(composite) = node.as_any().downcast_ref::<Composite>() {
+                let mut item =
+                    DrawItem::new(uuid, nodes.world_matrix(uuid)?, &composite.draw_state);
+                item.children = draw_items(nodes, &nodes.composite_render_list(uuid));
+                Some(item)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl Puppet {
+    /// Drawables in the current pose, in drawing order (see [`NodeTree::zsorted`]).
+    ///
+    /// Drawables that are disabled or have a disabled ancestor are left out, and so are parts with an empty mesh as they draw nothing.
+    /// Parameters and physics are taken into account
+    /// as long as they were applied with [`Puppet::set_params`] or [`Puppet::update_physics`].
+    pub fn scene(&self) -> Vec<DrawItem> {
+        draw_items(&self.nodes, &self.nodes.zsorted())
+    }
+
+    /// [`Puppet::scene`] as JSON, for renderers that aren't written in Rust.
+    #[cfg(feature = "serde")]
+    pub fn export_scene(&self) -> serde_json::Value {
+        serde_json::to_value(self.scene()).expect("draw items are always serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::ModelBuilder;
+    use crate::mesh::Mesh;
+    use crate::model::ModelTexture;
+    use crate::nodes::node::NodeState;
+
+    fn part(uuid: u32) -> Part {
+        let node_state = NodeState::new(NodeUuid(uuid), format!("Part {uuid}"));
+        Part::new(node_state, Mesh::quad().size(8, 8).build(), [0; 3])
+    }
+
+    #[test]
+    fn parts_under_a_disabled_node_are_left_out() {
+        let root = NodeUuid(1);
+        let mut builder = ModelBuilder::new(NodeState::new(root, "Root"));
+        builder.add_texture(ModelTexture {
+            format: image::ImageFormat::Png,
+            data: Vec::new(),
+        });
+        let mut group = NodeState::new(NodeUuid(2), "Group");
+        group.enabled = false;
+        builder.add_node(root, group).unwrap();
+        builder.add_node(NodeUuid(2), part(3)).unwrap();
+        builder.add_node(root, part(4)).unwrap();
+
+        let puppet = builder.build().puppet;
+        let uuids: Vec<_> = puppet.scene().iter().map(|item| item.uuid).collect();
+        assert_eq!(uuids, [NodeUuid(4)]);
+    }
+}